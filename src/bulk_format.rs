@@ -1,17 +1,24 @@
-use crate::config::Config;
+use crate::config::{resolve_config_file, Config, FileHead};
+use crate::daemon::config_cache::CONFIG_CACHE;
+use crate::daemon::interface::{
+    self, BulkFormatProgress, BulkFormatTotals, FileDiff, FileResult, FileStatus,
+};
 use crate::debug_long;
+use crate::diff::unified_diff_hunks;
 use crate::handle_plugin::run::run;
 use crate::log::DAEMON_THREAD_START;
 use crate::path_utils::{normalize_path, to_wasm_path};
+use crate::result_cache::{ResultCache, ResultCacheEntry};
 use anyhow::{anyhow, Context, Result};
 use foro_plugin_utils::data_json_utils::JsonGetter;
-use ignore::overrides::OverrideBuilder;
+use ignore::overrides::{Override, OverrideBuilder};
 use ignore::{WalkBuilder, WalkState};
-use log::{error, info, trace};
+use log::{error, info, trace, warn};
 use serde_json::json;
+use std::collections::HashMap;
 use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 use std::time::Instant;
 use std::{fs, io, thread};
@@ -22,44 +29,141 @@ pub struct BulkFormatOption {
     pub threads: usize,
     pub use_default_ignore: bool,
     pub current_dir: PathBuf,
+    /// Don't write formatted content, only report which files would change
+    pub check: bool,
+    /// Don't write formatted content, collect diffs of the changes instead
+    pub diff: bool,
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+/// Resolves the config to use for each directory, caching the loaded configs.
+///
+/// A given config file always wins. Otherwise, the nearest project config is used,
+/// falling back to the global config.
+pub struct ConfigResolver {
+    given_config_file: Option<PathBuf>,
+    cache_dir: PathBuf,
+    config_files: Mutex<HashMap<PathBuf, Option<PathBuf>>>,
+    configs: Mutex<HashMap<Option<PathBuf>, Arc<Config>>>,
+}
+
+impl ConfigResolver {
+    pub fn new(given_config_file: Option<PathBuf>, cache_dir: &Path) -> Self {
+        Self {
+            given_config_file,
+            cache_dir: cache_dir.to_path_buf(),
+            config_files: Mutex::new(HashMap::new()),
+            configs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register an already loaded config, so that it isn't looked up again.
+    pub fn insert(&self, config_file: Option<PathBuf>, config: Arc<Config>) {
+        self.configs.lock().unwrap().insert(config_file, config);
+    }
+
+    pub fn config_for(&self, dir: &Path) -> Result<Arc<Config>> {
+        let config_file = self
+            .config_files
+            .lock()
+            .unwrap()
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| resolve_config_file(self.given_config_file.as_deref(), dir))
+            .clone();
+
+        if let Some(config) = self.configs.lock().unwrap().get(&config_file) {
+            return Ok(config.clone());
+        }
+
+        let (config, _) = CONFIG_CACHE.load(config_file.as_deref(), Some(&self.cache_dir))?;
+
+        self.configs
+            .lock()
+            .unwrap()
+            .insert(config_file, config.clone());
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct BulkFormatSummary {
     pub changed_count: usize,
     pub unchanged_count: usize,
     pub ignored_count: usize,
     pub error_count: usize,
+    pub diffs: Vec<(PathBuf, String)>,
+    /// The outcome of each file, sorted by path
+    pub files: Vec<FileResult>,
+    pub cancelled: bool,
 }
 
 impl BulkFormatSummary {
     pub fn processed_count(&self) -> usize {
         self.changed_count + self.unchanged_count + self.ignored_count + self.error_count
     }
+
+    /// Count the outcome of a file and add it to the files.
+    pub fn record(&mut self, result: FileResult) {
+        match result.status {
+            FileStatus::Changed => self.changed_count += 1,
+            FileStatus::Unchanged => self.unchanged_count += 1,
+            FileStatus::Ignored => self.ignored_count += 1,
+            FileStatus::Error => self.error_count += 1,
+        }
+        self.files.push(result);
+    }
+}
+
+impl From<BulkFormatSummary> for interface::BulkFormatSummary {
+    fn from(summary: BulkFormatSummary) -> Self {
+        Self {
+            total_count: summary.processed_count(),
+            changed_count: summary.changed_count,
+            unchanged_count: summary.unchanged_count,
+            ignored_count: summary.ignored_count,
+            error_count: summary.error_count,
+            diffs: summary
+                .diffs
+                .into_iter()
+                .map(|(path, hunks)| FileDiff { path, hunks })
+                .collect(),
+            files: summary.files,
+            cancelled: summary.cancelled,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum FormatFileOutcome {
-    Changed,
+#[derive(Debug, Clone)]
+pub(crate) enum FormatFileOutcome {
+    /// Holds the diff hunks if diff mode is enabled
+    Changed(Option<String>),
     Unchanged,
     Ignored,
 }
 
-fn format_file(
+/// Format a file with the rules of `config`. With a `result_cache`, files known to be
+/// formatted are skipped.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn format_file(
     path: &Path,
     current_dir: &Path,
     config: &Config,
     cache_path: &Path,
     use_cache: bool,
+    result_cache: Option<&ResultCache>,
+    check: bool,
+    diff: bool,
 ) -> Result<FormatFileOutcome> {
     info!("Formatting: {:?}", path);
 
-    let Some(rule) = config.find_matched_rule(path) else {
+    let rules = config.find_matched_rules(path, &FileHead::from_file(path));
+
+    if rules.is_empty() {
         info!("No rule matched, ignored: {:?}", path);
         return Ok(FormatFileOutcome::Ignored);
-    };
+    }
 
-    debug_long!("run rule: {:?}", rule);
+    debug_long!("run rules: {:?}", rules);
 
     let file = fs::File::open(path)?;
     let mut buf_reader = io::BufReader::new(file);
@@ -68,8 +172,17 @@ fn format_file(
 
     trace!("opened file: {:?}", path);
 
+    let cache_entry = result_cache.and_then(|cache| cache.entry(path, &rules, &content));
+    if cache_entry
+        .as_ref()
+        .is_some_and(ResultCacheEntry::is_formatted)
+    {
+        info!("Already formatted, skipped: {:?}", path);
+        return Ok(FormatFileOutcome::Unchanged);
+    }
+
     let res = run(
-        &rule.cmd,
+        &rules,
         json!({
             "wasm-current-dir":  to_wasm_path(current_dir)?,
             "os-current-dir": normalize_path(current_dir)?,
@@ -80,6 +193,7 @@ fn format_file(
         }),
         cache_path,
         use_cache,
+        !check && !diff,
     )?;
 
     debug_long!("{:?}", res);
@@ -93,12 +207,13 @@ fn format_file(
             "error" => {
                 return Err(match String::get_value_opt(&res, ["format-error"]) {
                     Some(format_error) => {
-                        anyhow!("File formatting failed for {:?}: {}", path, format_error)
+                        anyhow!("File formatting failed: {}", format_error)
+                    }
+                    None => {
+                        anyhow!(
+                            "Formatter plugin returned format-status=error without format-error"
+                        )
                     }
-                    None => anyhow!(
-                        "Formatter plugin returned format-status=error without format-error for {:?}",
-                        path
-                    ),
                 });
             }
             _ => {}
@@ -107,7 +222,7 @@ fn format_file(
 
     let outcome = if let Some(formatted) = String::get_value_opt(&res, ["formatted-content"]) {
         if formatted != content {
-            FormatFileOutcome::Changed
+            FormatFileOutcome::Changed(diff.then(|| unified_diff_hunks(&content, &formatted)))
         } else {
             FormatFileOutcome::Unchanged
         }
@@ -115,16 +230,95 @@ fn format_file(
         FormatFileOutcome::Unchanged
     };
 
+    if let (FormatFileOutcome::Unchanged, Some(cache_entry)) = (&outcome, &cache_entry) {
+        if let Err(err) = cache_entry.mark_formatted() {
+            warn!(
+                "Failed to cache the formatted result of {:?}: {err:#}",
+                path
+            );
+        }
+    }
+
     info!("Successfully formatted: {:?} ({:?})", path, outcome);
 
     Ok(outcome)
 }
 
+/// Overrides that ignore the paths in `default_ignore.txt`, relative to `root`.
+pub(crate) fn default_ignore_overrides(root: &Path) -> Override {
+    let default_ignore_content = include_str!("./default_ignore.txt");
+    let mut overrides_builder = OverrideBuilder::new(root);
+
+    for line in default_ignore_content.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        overrides_builder.add(&format!("!{line}")).unwrap();
+    }
+
+    overrides_builder.build().unwrap()
+}
+
+/// The path a walk error is about, if any.
+fn walk_error_path(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithLineNumber { err, .. } | ignore::Error::WithDepth { err, .. } => {
+            walk_error_path(err)
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn file_result(path: PathBuf, status: FileStatus) -> FileResult {
+    FileResult {
+        path,
+        status,
+        message: None,
+    }
+}
+
+/// Called with the progress of a bulk format, from the worker threads.
+pub type ProgressCallback = Arc<dyn Fn(BulkFormatProgress) + Send + Sync>;
+
+/// Lets the caller of a bulk format follow and stop it.
+#[derive(Clone, Default)]
+pub struct BulkFormatControl {
+    pub on_progress: Option<ProgressCallback>,
+    /// Once set, no more files are scheduled or formatted, and the summary only covers
+    /// the files done until then.
+    pub cancelled: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct Counters {
+    found: AtomicUsize,
+    changed: AtomicUsize,
+    unchanged: AtomicUsize,
+    ignored: AtomicUsize,
+    error: AtomicUsize,
+}
+
+impl Counters {
+    fn totals(&self) -> BulkFormatTotals {
+        BulkFormatTotals {
+            found_count: self.found.load(Ordering::SeqCst),
+            changed_count: self.changed.load(Ordering::SeqCst),
+            unchanged_count: self.unchanged.load(Ordering::SeqCst),
+            ignored_count: self.ignored.load(Ordering::SeqCst),
+            error_count: self.error.load(Ordering::SeqCst),
+        }
+    }
+}
+
 pub fn bulk_format(
     opt: &BulkFormatOption,
-    config: &Config,
+    configs: Arc<ConfigResolver>,
     cache_path: &Path,
     use_cache: bool,
+    control: BulkFormatControl,
 ) -> Result<BulkFormatSummary> {
     let (fst, rest) = opt.paths.split_first().context("No path given")?;
     let worker_count = opt.threads.max(1);
@@ -138,19 +332,7 @@ pub fn bulk_format(
     walk_builder.add_custom_ignore_filename(".foro-ignore");
 
     if opt.use_default_ignore {
-        let default_ignore_content = include_str!("./default_ignore.txt");
-        let mut overrides_builder = OverrideBuilder::new(&opt.current_dir);
-
-        for line in default_ignore_content.lines() {
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
-
-            overrides_builder.add(&format!("!{line}")).unwrap();
-        }
-
-        let overrides = overrides_builder.build().unwrap();
-        walk_builder.overrides(overrides);
+        walk_builder.overrides(default_ignore_overrides(&opt.current_dir));
     }
 
     let walk = walk_builder.build_parallel();
@@ -161,19 +343,28 @@ pub fn bulk_format(
     let (work_tx, work_rx) = mpsc::sync_channel::<PathBuf>(queue_capacity);
     let work_rx = Arc::new(Mutex::new(work_rx));
     let mut workers = Vec::with_capacity(worker_count);
-    let changed_count = Arc::new(AtomicUsize::new(0));
-    let unchanged_count = Arc::new(AtomicUsize::new(0));
-    let ignored_count = Arc::new(AtomicUsize::new(0));
-    let error_count = Arc::new(AtomicUsize::new(0));
+    let counters = Arc::new(Counters::default());
+    let diffs = Arc::new(Mutex::new(Vec::new()));
+    let files = Arc::new(Mutex::new(Vec::new()));
+    let check = opt.check;
+    let diff = opt.diff;
+    let BulkFormatControl {
+        on_progress,
+        cancelled,
+    } = control;
+
+    let result_cache = use_cache.then(|| Arc::new(ResultCache::new(cache_path)));
 
     for _ in 0..worker_count {
-        let config = config.clone();
+        let configs = configs.clone();
+        let result_cache = result_cache.clone();
         let cache_path = cache_path.to_path_buf();
-        let changed_count = changed_count.clone();
-        let unchanged_count = unchanged_count.clone();
-        let ignored_count = ignored_count.clone();
-        let error_count = error_count.clone();
+        let counters = counters.clone();
+        let diffs = diffs.clone();
+        let files = files.clone();
         let work_rx = work_rx.clone();
+        let on_progress = on_progress.clone();
+        let cancelled = cancelled.clone();
 
         workers.push(thread::spawn(move || {
             DAEMON_THREAD_START.with(|start| {
@@ -191,39 +382,83 @@ pub fn bulk_format(
                     Err(_) => break,
                 };
 
-                let res = format_file(
-                    &path,
-                    path.parent().unwrap(),
-                    &config,
-                    &cache_path,
-                    use_cache,
-                );
-
-                match res {
-                    Ok(FormatFileOutcome::Changed) => {
-                        changed_count.fetch_add(1, Ordering::SeqCst);
+                // The queue is drained rather than left, so that the walker never blocks on it
+                if cancelled.load(Ordering::SeqCst) {
+                    continue;
+                }
+
+                if let Some(on_progress) = &on_progress {
+                    on_progress(BulkFormatProgress::Started(path.clone()));
+                }
+
+                let res = configs
+                    .config_for(path.parent().unwrap())
+                    .and_then(|config| {
+                        format_file(
+                            &path,
+                            path.parent().unwrap(),
+                            &config,
+                            &cache_path,
+                            use_cache,
+                            result_cache.as_deref(),
+                            check,
+                            diff,
+                        )
+                    });
+
+                let result = match res {
+                    Ok(FormatFileOutcome::Changed(hunks)) => {
+                        counters.changed.fetch_add(1, Ordering::SeqCst);
+                        if let Some(hunks) = hunks {
+                            diffs.lock().unwrap().push((path.clone(), hunks));
+                        }
+                        file_result(path, FileStatus::Changed)
                     }
                     Ok(FormatFileOutcome::Unchanged) => {
-                        unchanged_count.fetch_add(1, Ordering::SeqCst);
+                        counters.unchanged.fetch_add(1, Ordering::SeqCst);
+                        file_result(path, FileStatus::Unchanged)
                     }
                     Ok(FormatFileOutcome::Ignored) => {
-                        ignored_count.fetch_add(1, Ordering::SeqCst);
+                        counters.ignored.fetch_add(1, Ordering::SeqCst);
+                        file_result(path, FileStatus::Ignored)
                     }
                     Err(err) => {
-                        error_count.fetch_add(1, Ordering::SeqCst);
+                        counters.error.fetch_add(1, Ordering::SeqCst);
                         error!("Error formatting file {}: {err:#}", path.display());
+                        FileResult {
+                            path,
+                            status: FileStatus::Error,
+                            message: Some(format!("{err:#}")),
+                        }
                     }
+                };
+
+                if let Some(on_progress) = &on_progress {
+                    on_progress(BulkFormatProgress::Finished {
+                        result: result.clone(),
+                        totals: counters.totals(),
+                    });
                 }
+
+                files.lock().unwrap().push(result);
             }
         }));
     }
 
     let walk_tx = work_tx.clone();
-    let walk_error_count = error_count.clone();
+    let walk_counters = counters.clone();
+    let walk_files = files.clone();
+    let walk_cancelled = cancelled.clone();
     walk.run(move || {
         let work_tx = walk_tx.clone();
-        let error_count = walk_error_count.clone();
+        let counters = walk_counters.clone();
+        let files = walk_files.clone();
+        let cancelled = walk_cancelled.clone();
         Box::new(move |entry_res| {
+            if cancelled.load(Ordering::SeqCst) {
+                return WalkState::Quit;
+            }
+
             match entry_res {
                 Ok(dir_entry) => {
                     let path = dir_entry.path().to_path_buf();
@@ -232,14 +467,24 @@ pub fn bulk_format(
                         return WalkState::Continue;
                     }
 
+                    counters.found.fetch_add(1, Ordering::SeqCst);
+
                     if let Err(err) = work_tx.send(path) {
-                        error_count.fetch_add(1, Ordering::SeqCst);
+                        counters.error.fetch_add(1, Ordering::SeqCst);
                         error!("Error scheduling file: {}", err);
                     }
                 }
                 Err(err) => {
-                    error_count.fetch_add(1, Ordering::SeqCst);
+                    counters.error.fetch_add(1, Ordering::SeqCst);
                     error!("Error reading entry: {}", err);
+
+                    if let Some(path) = walk_error_path(&err) {
+                        files.lock().unwrap().push(FileResult {
+                            path: path.to_path_buf(),
+                            status: FileStatus::Error,
+                            message: Some(err.to_string()),
+                        });
+                    }
                 }
             }
 
@@ -253,10 +498,26 @@ pub fn bulk_format(
         worker.join().unwrap();
     }
 
+    if let Some(result_cache) = &result_cache {
+        if let Err(err) = result_cache.prune() {
+            warn!("Failed to prune the cached results: {err:#}");
+        }
+    }
+
+    let mut diffs = std::mem::take(&mut *diffs.lock().unwrap());
+    diffs.sort();
+    let mut files = std::mem::take(&mut *files.lock().unwrap());
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let totals = counters.totals();
+
     Ok(BulkFormatSummary {
-        changed_count: changed_count.load(Ordering::SeqCst),
-        unchanged_count: unchanged_count.load(Ordering::SeqCst),
-        ignored_count: ignored_count.load(Ordering::SeqCst),
-        error_count: error_count.load(Ordering::SeqCst),
+        changed_count: totals.changed_count,
+        unchanged_count: totals.unchanged_count,
+        ignored_count: totals.ignored_count,
+        error_count: totals.error_count,
+        diffs,
+        files,
+        cancelled: cancelled.load(Ordering::SeqCst),
     })
 }
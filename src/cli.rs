@@ -7,8 +7,11 @@ mod cache;
 mod config;
 mod daemon;
 mod format;
+mod hook;
 mod install;
 mod internal;
+mod lsp;
+mod watch;
 
 use format::*;
 
@@ -16,8 +19,12 @@ use crate::build_info;
 use crate::cli::cache::{cache_execute_with_args, CacheArgs};
 use crate::cli::config::{config_execute_with_args, ConfigArgs};
 use crate::cli::daemon::{daemon_execute_with_args, DaemonArgs};
+use crate::cli::hook::{hook_execute_with_args, HookArgs};
 use crate::cli::install::{install_execute_with_args, InstallArgs};
 use crate::cli::internal::{internal_execute_with_args, InternalArgs};
+use crate::cli::lsp::{lsp_execute_with_args, LspArgs};
+use crate::cli::watch::{watch_execute_with_args, WatchArgs};
+use crate::config::resolve_config_file;
 use crate::daemon::interface::DaemonExecutionOptions;
 use crate::log::init_env_logger;
 use log::trace;
@@ -29,7 +36,10 @@ pub enum SubCommands {
     Config(ConfigArgs),
     Daemon(DaemonArgs),
     Format(FormatArgs),
+    Hook(HookArgs),
     Install(InstallArgs),
+    Lsp(LspArgs),
+    Watch(WatchArgs),
     #[clap(hide = true)]
     Internal(InternalArgs),
 }
@@ -60,6 +70,19 @@ pub struct GlobalOptions {
     pub ignore_build_id_mismatch: bool,
 }
 
+impl GlobalOptions {
+    /// The config file to use for commands run in the current directory.
+    ///
+    /// Returns the given `--config-file` if any, otherwise the nearest project config
+    /// found from the current directory. `None` means the global config file.
+    pub fn project_config_file(&self) -> Option<PathBuf> {
+        match std::env::current_dir() {
+            Ok(current_dir) => resolve_config_file(self.config_file.as_deref(), &current_dir),
+            Err(_) => self.config_file.clone(),
+        }
+    }
+}
+
 impl From<&GlobalOptions> for DaemonExecutionOptions {
     fn from(value: &GlobalOptions) -> Self {
         Self {
@@ -108,7 +131,10 @@ pub fn execute_with_args(args: Command) -> Result<()> {
         SubCommands::Config(s_args) => config_execute_with_args(s_args, global_options),
         SubCommands::Daemon(s_args) => daemon_execute_with_args(s_args, global_options),
         SubCommands::Format(s_args) => format_execute_with_args(s_args, global_options),
+        SubCommands::Hook(s_args) => hook_execute_with_args(s_args, global_options),
         SubCommands::Install(s_args) => install_execute_with_args(s_args, global_options),
+        SubCommands::Lsp(s_args) => lsp_execute_with_args(s_args, global_options),
+        SubCommands::Watch(s_args) => watch_execute_with_args(s_args, global_options),
         SubCommands::Internal(s_args) => internal_execute_with_args(s_args, global_options),
     }?;
 
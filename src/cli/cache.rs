@@ -21,7 +21,7 @@ pub fn cache_clean_execute_with_args(
     global_options: GlobalOptions,
 ) -> Result<()> {
     let (_, cache_dir) = load_config_and_cache(
-        global_options.config_file.as_deref(),
+        global_options.project_config_file().as_deref(),
         global_options.cache_dir.as_deref(),
     )?;
 
@@ -68,7 +68,7 @@ pub fn cache_remove_execute_with_args(
     global_options: GlobalOptions,
 ) -> Result<()> {
     let (_, cache_dir) = load_config_and_cache(
-        global_options.config_file.as_deref(),
+        global_options.project_config_file().as_deref(),
         global_options.cache_dir.as_deref(),
     )?;
 
@@ -127,7 +127,7 @@ pub fn cache_dir_execute_with_args(
     global_options: GlobalOptions,
 ) -> Result<()> {
     let (_, cache_dir) = load_config_and_cache(
-        global_options.config_file.as_deref(),
+        global_options.project_config_file().as_deref(),
         global_options.cache_dir.as_deref(),
     )?;
 
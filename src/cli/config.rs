@@ -1,7 +1,8 @@
 use crate::cli::GlobalOptions;
-use crate::config::get_or_create_default_config;
+use crate::config::{config_schema, get_or_create_default_config, validate_file, Severity};
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 pub enum ConfigSubCommands {
@@ -9,6 +10,8 @@ pub enum ConfigSubCommands {
     Show(ConfigShowArgs),
     Default(ConfigDefaultArgs),
     Edit(ConfigEditArgs),
+    Validate(ConfigValidateArgs),
+    Schema(ConfigSchemaArgs),
 }
 
 #[derive(Parser, Debug)]
@@ -32,6 +35,15 @@ pub struct ConfigEditArgs {
     pub editor: Option<String>,
 }
 
+#[derive(Parser, Debug)]
+pub struct ConfigValidateArgs {
+    /// Config file to validate (defaults to the config that would be used)
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ConfigSchemaArgs {}
+
 pub fn config_execute_with_args(args: ConfigArgs, global_options: GlobalOptions) -> Result<()> {
     match args.subcommand {
         ConfigSubCommands::Path(s_args) => config_path_execute_with_args(s_args, global_options),
@@ -40,6 +52,12 @@ pub fn config_execute_with_args(args: ConfigArgs, global_options: GlobalOptions)
             config_default_execute_with_args(s_args, global_options)
         }
         ConfigSubCommands::Edit(s_args) => config_edit_execute_with_args(s_args, global_options),
+        ConfigSubCommands::Validate(s_args) => {
+            config_validate_execute_with_args(s_args, global_options)
+        }
+        ConfigSubCommands::Schema(s_args) => {
+            config_schema_execute_with_args(s_args, global_options)
+        }
     }
 }
 
@@ -48,7 +66,7 @@ pub fn config_path_execute_with_args(
     global_options: GlobalOptions,
 ) -> Result<()> {
     let config_file = global_options
-        .config_file
+        .project_config_file()
         .or_else(get_or_create_default_config)
         .context("Failed to get config file path")?;
 
@@ -62,7 +80,7 @@ pub fn config_show_execute_with_args(
     global_options: GlobalOptions,
 ) -> Result<()> {
     let config_file = global_options
-        .config_file
+        .project_config_file()
         .or_else(get_or_create_default_config)
         .context("Failed to get config file path")?;
 
@@ -88,7 +106,7 @@ pub fn config_edit_execute_with_args(
     global_options: GlobalOptions,
 ) -> Result<()> {
     let config_file = global_options
-        .config_file
+        .project_config_file()
         .or_else(get_or_create_default_config)
         .context("Failed to get config file path")?;
 
@@ -105,3 +123,41 @@ pub fn config_edit_execute_with_args(
 
     Ok(())
 }
+
+pub fn config_validate_execute_with_args(
+    args: ConfigValidateArgs,
+    global_options: GlobalOptions,
+) -> Result<()> {
+    let config_file = args
+        .path
+        .or_else(|| global_options.project_config_file())
+        .or_else(get_or_create_default_config)
+        .context("Failed to get config file path")?;
+
+    let diagnostics = validate_file(&config_file)?;
+
+    for diagnostic in &diagnostics {
+        eprintln!("{diagnostic}");
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+
+    if errors > 0 {
+        return Err(anyhow!("{errors} error(s) found in {config_file:?}"));
+    }
+
+    println!("Config is valid: {config_file:?}");
+
+    Ok(())
+}
+
+pub fn config_schema_execute_with_args(
+    _args: ConfigSchemaArgs,
+    _global_options: GlobalOptions,
+) -> Result<()> {
+    println!("{}", config_schema());
+    Ok(())
+}
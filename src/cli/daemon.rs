@@ -23,7 +23,7 @@ pub fn daemon_start_execute_with_args(
     global_options: GlobalOptions,
 ) -> Result<()> {
     let (_, socket_dir) = load_config_and_socket(
-        global_options.config_file.as_deref(),
+        global_options.project_config_file().as_deref(),
         global_options.socket_dir.as_deref(),
     )?;
 
@@ -59,6 +59,18 @@ pub struct DaemonFormatCliArgs {
     /// Path to format
     pub path: PathBuf,
     pub content: String,
+    /// Only report whether the file would change
+    #[clap(long)]
+    pub check: bool,
+    /// Return a diff of the changes instead of writing the file
+    #[clap(long)]
+    pub diff: bool,
+    /// Return the formatted content instead of writing the file
+    #[clap(long)]
+    pub return_content: bool,
+    /// Return the edits that format the content instead of writing the file
+    #[clap(long)]
+    pub return_edits: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -67,6 +79,12 @@ pub struct DaemonBulkFormatCliArgs {
     pub paths: Vec<PathBuf>,
     /// Number of threads to use
     pub threads: usize,
+    /// Only report which files would change
+    #[clap(long)]
+    pub check: bool,
+    /// Return diffs of the changes instead of writing the files
+    #[clap(long)]
+    pub diff: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -83,11 +101,19 @@ impl From<DaemonServerCommands> for DaemonCommands {
             DaemonServerCommands::Format(args) => DaemonCommands::Format(DaemonFormatArgs {
                 path: args.path,
                 content: args.content,
+                check: args.check,
+                diff: args.diff,
+                return_content: args.return_content,
+                return_edits: args.return_edits,
+                range: None,
             }),
             DaemonServerCommands::BulkFormat(args) => {
                 DaemonCommands::BulkFormat(DaemonBulkFormatArgs {
                     paths: args.paths,
                     threads: args.threads,
+                    check: args.check,
+                    diff: args.diff,
+                    progress: false,
                 })
             }
             DaemonServerCommands::Stop => DaemonCommands::Stop,
@@ -115,7 +141,7 @@ pub fn daemon_restart_execute_with_args(
     global_options: GlobalOptions,
 ) -> Result<()> {
     let (_, socket_dir) = load_config_and_socket(
-        global_options.config_file.as_deref(),
+        global_options.project_config_file().as_deref(),
         global_options.socket_dir.as_deref(),
     )?;
 
@@ -146,7 +172,7 @@ pub fn daemon_execute_with_args(args: DaemonArgs, global_options: GlobalOptions)
         }
         DaemonSubcommands::ServerCommands(command) => {
             let (_, socket_dir) = load_config_and_socket(
-                global_options.config_file.as_deref(),
+                global_options.project_config_file().as_deref(),
                 global_options.socket_dir.as_deref(),
             )?;
 
@@ -1,27 +1,99 @@
+use crate::bulk_format::{self, file_result};
 use crate::cli::GlobalOptions;
 use crate::config::load_paths;
-use crate::daemon::client::{ensure_daemon_running, run_command as daemon_run_command};
+use crate::daemon::client::{
+    ensure_daemon_running, format_bulk_check_message, format_bulk_success_message,
+    print_bulk_format_summary, print_file_error, print_file_errors, relative_to_cwd,
+    run_command as daemon_run_command, DaemonConnection,
+};
 use crate::daemon::interface::{
-    DaemonBulkFormatArgs, DaemonCommands, DaemonExecutionOptions, DaemonFormatArgs,
-    DaemonSocketPath,
+    BulkFormatProgress, BulkFormatSummary, DaemonBulkFormatArgs, DaemonBulkFormatResponse,
+    DaemonCommands, DaemonExecutionOptions, DaemonFormatArgs, DaemonFormatResponse, DaemonResponse,
+    DaemonSocketPath, FileResult, FileStatus, TargetRange,
 };
-use anyhow::Result;
+use crate::diff::unified_diff_hunks;
+use crate::fs_utils::write_formatted;
+use crate::git::GitRepo;
+use crate::report::{render_report, ReportFormat};
+use anyhow::{anyhow, bail, Result};
 use clap::Parser;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{error, info};
+use std::fs;
+use std::io::Read;
 use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[derive(Parser, Debug)]
 pub struct FormatArgs {
-    /// Paths to format
+    /// Paths to format. With `--staged` or `--changed(-since)`, only files under them are picked.
     #[clap(default_value = ".")]
     pub paths: Vec<PathBuf>,
     /// Number of threads to use
     #[clap(short, long, default_value = "0")]
     pub threads: usize,
+    /// Don't write files, only report the ones that would be changed.
+    /// Exits with a non-zero status if any file is not formatted.
+    #[clap(long)]
+    pub check: bool,
+    /// Don't write files, print a unified diff of the changes instead.
+    /// Combine with `--check` to also exit with a non-zero status on changes.
+    #[clap(long)]
+    pub diff: bool,
+    /// Read the content to format from stdin and write the result to stdout.
+    /// The given path is used to select the rule and plugin config, and doesn't need to exist.
+    /// With `--check` or `--diff`, the content isn't written, only the status (or the diff).
+    #[clap(long, value_name = "PATH", conflicts_with = "paths")]
+    pub stdin_filepath: Option<PathBuf>,
+    /// Format the staged content of the staged files, and stage the result.
+    /// Unstaged changes are left untouched.
+    #[clap(long, conflicts_with_all = ["stdin_filepath", "changed", "changed_since"])]
+    pub staged: bool,
+    /// Only format files changed in the git work tree or index, and untracked files
+    #[clap(long, conflicts_with_all = ["stdin_filepath", "changed_since"])]
+    pub changed: bool,
+    /// Only format files changed since the given git revision, and untracked files
+    #[clap(long, value_name = "REV", conflicts_with = "stdin_filepath")]
+    pub changed_since: Option<String>,
+    /// Don't write files, print the edits that would format the file (or stdin) as JSON.
+    /// Positions are 0-based lines and UTF-16 columns, like in LSP.
+    #[clap(long, conflicts_with_all = ["check", "diff", "staged", "changed", "changed_since"])]
+    pub edits: bool,
+    /// Only format the given lines (1-based, inclusive) of a single file or stdin
+    #[clap(long, value_name = "START:END", value_parser = parse_span, conflicts_with_all = ["bytes", "staged", "changed", "changed_since"])]
+    pub lines: Option<(usize, usize)>,
+    /// Only format the given byte range (end exclusive) of a single file or stdin
+    #[clap(long, value_name = "START:END", value_parser = parse_span, conflicts_with_all = ["staged", "changed", "changed_since"])]
+    pub bytes: Option<(usize, usize)>,
+    /// Print a report of each file to stdout instead of the usual messages, e.g. for CI annotations.
+    /// With `--check`, unformatted files are reported as failures.
+    #[clap(long, value_enum, value_name = "FORMAT", conflicts_with_all = ["stdin_filepath", "diff", "edits", "lines", "bytes"])]
+    pub output_format: Option<ReportFormat>,
+}
+
+fn parse_span(s: &str) -> Result<(usize, usize), String> {
+    let (start, end) = s
+        .split_once(':')
+        .ok_or_else(|| "expected START:END".to_string())?;
+    let parse = |n: &str| n.parse::<usize>().map_err(|err| format!("{n:?}: {err}"));
+
+    Ok((parse(start)?, parse(end)?))
+}
+
+impl FormatArgs {
+    fn range(&self) -> Option<TargetRange> {
+        match (self.lines, self.bytes) {
+            (Some((start, end)), _) => Some(TargetRange::Lines { start, end }),
+            (_, Some((start, end))) => Some(TargetRange::Bytes { start, end }),
+            _ => None,
+        }
+    }
 }
 
 pub fn format_execute_with_args(args: FormatArgs, global_options: GlobalOptions) -> Result<()> {
     let (_, _, socket_dir) = load_paths(
-        global_options.config_file.as_deref(),
+        global_options.project_config_file().as_deref(),
         global_options.cache_dir.as_deref(),
         global_options.socket_dir.as_deref(),
     )?;
@@ -31,36 +103,304 @@ pub fn format_execute_with_args(args: FormatArgs, global_options: GlobalOptions)
 
     ensure_daemon_running(&socket, &daemon_options)?;
 
-    // If only one path is given and it's a file, use Format command
-    if args.paths.len() == 1 && args.paths[0].is_file() {
+    if let Some(stdin_filepath) = &args.stdin_filepath {
+        let mut content = String::new();
+        std::io::stdin().read_to_string(&mut content)?;
+
+        daemon_run_command(
+            DaemonCommands::Format(DaemonFormatArgs {
+                path: stdin_filepath.clone(),
+                content,
+                check: args.check,
+                diff: args.diff,
+                return_content: !args.edits && !args.check && !args.diff,
+                return_edits: args.edits,
+                range: args.range(),
+            }),
+            daemon_options,
+            &socket,
+            false,
+        )?;
+    } else if args.staged {
+        let repo = GitRepo::discover(&std::env::current_dir()?)?;
+        format_staged(&args, &repo, &daemon_options, &socket)?;
+    } else if args.changed || args.changed_since.is_some() {
+        let repo = GitRepo::discover(&std::env::current_dir()?)?;
+        let paths = match &args.changed_since {
+            Some(rev) => repo.changed_since(rev, &args.paths)?,
+            None => repo.changed(&args.paths)?,
+        };
+
+        if paths.is_empty() {
+            eprintln!("No changed files.");
+            return Ok(());
+        }
+
+        bulk_format(
+            DaemonBulkFormatArgs {
+                paths: paths.into_iter().collect(),
+                threads: threads(args.threads),
+                check: args.check,
+                diff: args.diff,
+                progress: true,
+            },
+            args.output_format,
+            daemon_options,
+            &socket,
+        )?;
+    } else if args.paths.len() == 1 && args.paths[0].is_file() && args.output_format.is_none() {
+        // If only one path is given and it's a file, use Format command
         let content = std::fs::read_to_string(&args.paths[0])?;
         daemon_run_command(
             DaemonCommands::Format(DaemonFormatArgs {
                 path: args.paths[0].clone(),
                 content,
+                check: args.check,
+                diff: args.diff,
+                return_content: false,
+                return_edits: args.edits,
+                range: args.range(),
             }),
             daemon_options,
             &socket,
             false,
         )?;
+    } else if args.range().is_some() || args.edits {
+        bail!("--lines, --bytes and --edits need a single file or --stdin-filepath");
     } else {
         // Otherwise, use BulkFormat command
-        let threads = if args.threads == 0 {
-            num_cpus::get()
-        } else {
-            args.threads
-        };
-
-        daemon_run_command(
-            DaemonCommands::BulkFormat(DaemonBulkFormatArgs {
+        bulk_format(
+            DaemonBulkFormatArgs {
                 paths: args.paths,
-                threads,
-            }),
+                threads: threads(args.threads),
+                check: args.check,
+                diff: args.diff,
+                progress: true,
+            },
+            args.output_format,
             daemon_options,
             &socket,
-            true,
         )?;
     }
 
     Ok(())
 }
+
+fn threads(threads: usize) -> usize {
+    if threads == 0 {
+        num_cpus::get()
+    } else {
+        threads
+    }
+}
+
+/// Shows the progress of a bulk format on stderr (if it's a terminal),
+/// and prints the errors as they happen.
+struct ProgressReporter {
+    bar: ProgressBar,
+    print_errors: bool,
+}
+
+impl ProgressReporter {
+    fn new(print_errors: bool) -> Self {
+        let bar = ProgressBar::new(0).with_style(
+            ProgressStyle::with_template("[{bar:30}] {pos}/{len} {wide_msg}")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+
+        Self { bar, print_errors }
+    }
+
+    fn update(&self, event: BulkFormatProgress) {
+        match event {
+            BulkFormatProgress::Started(path) => {
+                self.bar
+                    .set_message(relative_to_cwd(&path).display().to_string());
+            }
+            BulkFormatProgress::Finished { result, totals } => {
+                let processed = totals.processed_count();
+                self.bar
+                    .set_length(totals.found_count.max(processed) as u64);
+                self.bar.set_position(processed as u64);
+
+                if self.print_errors {
+                    self.bar.suspend(|| print_file_error(&result));
+                }
+            }
+        }
+    }
+}
+
+/// Cancel the request `id` on Ctrl-C, so that the daemon stops and replies with a partial summary.
+/// A second Ctrl-C exits right away.
+fn cancel_on_interrupt(
+    daemon: &DaemonConnection,
+    id: u64,
+    daemon_options: DaemonExecutionOptions,
+) -> Result<()> {
+    let sender = daemon.sender();
+    let interrupted = AtomicBool::new(false);
+
+    ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+
+        eprintln!("Cancelling...");
+        if let Err(err) = sender.send(DaemonCommands::Cancel { id }, daemon_options.clone()) {
+            eprintln!("Failed to cancel: {err:#}");
+            process::exit(130);
+        }
+    })?;
+
+    Ok(())
+}
+
+fn bulk_format(
+    bulk_args: DaemonBulkFormatArgs,
+    output_format: Option<ReportFormat>,
+    daemon_options: DaemonExecutionOptions,
+    socket: &DaemonSocketPath,
+) -> Result<()> {
+    let (check, diff) = (bulk_args.check, bulk_args.diff);
+    // A report has the errors, so they aren't printed to stderr too
+    let progress = ProgressReporter::new(output_format.is_none());
+
+    let mut daemon = DaemonConnection::connect(socket)?;
+    let id = daemon.send(
+        DaemonCommands::BulkFormat(bulk_args),
+        daemon_options.clone(),
+    )?;
+    cancel_on_interrupt(&daemon, id, daemon_options)?;
+
+    let response = daemon.wait_with_progress(id, |event| progress.update(event));
+
+    progress.bar.finish_and_clear();
+
+    match response? {
+        DaemonResponse::BulkFormat(DaemonBulkFormatResponse::Success(summary)) => {
+            match output_format {
+                Some(output_format) => report(&summary, output_format, check),
+                None => print_bulk_format_summary(&summary, check, diff),
+            }
+        }
+        DaemonResponse::BulkFormat(DaemonBulkFormatResponse::Error(err)) => Err(anyhow!(err)),
+        other => bail!("Unexpected response from the daemon: {other:?}"),
+    }
+}
+
+/// Print the report, and fail like the text output would.
+fn report(summary: &BulkFormatSummary, output_format: ReportFormat, check: bool) -> Result<()> {
+    print!("{}", render_report(output_format, summary, check));
+
+    if summary.cancelled {
+        bail!("Cancelled: {}", format_bulk_success_message(summary));
+    } else if check {
+        if summary.error_count > 0 || summary.changed_count > 0 {
+            bail!("Check failed: {}", format_bulk_check_message(summary));
+        }
+    } else if summary.error_count > 0 {
+        bail!(
+            "Formatted with errors: {}",
+            format_bulk_success_message(summary)
+        );
+    }
+
+    Ok(())
+}
+
+/// Format the staged content of the staged files and stage the result.
+///
+/// The work tree file is only updated if it has no unstaged changes,
+/// so that unstaged hunks are never touched.
+fn format_staged(
+    args: &FormatArgs,
+    repo: &GitRepo,
+    daemon_options: &DaemonExecutionOptions,
+    socket: &DaemonSocketPath,
+) -> Result<()> {
+    let mut summary = bulk_format::BulkFormatSummary::default();
+
+    // All the files are sent first, so that the daemon formats them in parallel
+    let mut daemon = DaemonConnection::connect(socket)?;
+    let mut requests = Vec::new();
+
+    for path in repo.staged_paths(&args.paths)? {
+        let Some((file, staged)) = repo.read_staged(&path)? else {
+            info!("Staged entry is not a regular file, ignored: {path:?}");
+            summary.record(file_result(path, FileStatus::Ignored));
+            continue;
+        };
+        let Ok(content) = String::from_utf8(staged) else {
+            info!("Staged content is not UTF-8, ignored: {path:?}");
+            summary.record(file_result(path, FileStatus::Ignored));
+            continue;
+        };
+
+        let id = daemon.send(
+            DaemonCommands::Format(DaemonFormatArgs {
+                path: path.clone(),
+                content: content.clone(),
+                check: false,
+                diff: false,
+                return_content: true,
+                return_edits: false,
+                range: None,
+            }),
+            daemon_options.clone(),
+        )?;
+        requests.push((id, path, file, content));
+    }
+
+    for (id, path, file, content) in requests {
+        let formatted = match daemon.wait(id)? {
+            DaemonResponse::Format(DaemonFormatResponse::Content(formatted)) => formatted,
+            DaemonResponse::Format(DaemonFormatResponse::Ignored(_)) => {
+                summary.record(file_result(path, FileStatus::Ignored));
+                continue;
+            }
+            DaemonResponse::Format(DaemonFormatResponse::Error(err)) => {
+                error!("Error formatting file {}: {err}", path.display());
+                summary.record(FileResult {
+                    path,
+                    status: FileStatus::Error,
+                    message: Some(err),
+                });
+                continue;
+            }
+            other => bail!("Unexpected response from the daemon: {other:?}"),
+        };
+
+        if formatted == content {
+            summary.record(file_result(path, FileStatus::Unchanged));
+            continue;
+        }
+
+        if args.diff {
+            summary
+                .diffs
+                .push((path.clone(), unified_diff_hunks(&content, &formatted)));
+        } else if !args.check {
+            // A file deleted from the work tree counts as an unstaged change
+            let has_unstaged_changes = fs::read(&path).ok().as_deref() != Some(content.as_bytes());
+
+            repo.stage(&file, formatted.as_bytes())?;
+
+            if !has_unstaged_changes {
+                write_formatted(&path, content.as_bytes(), formatted.as_bytes())?;
+            }
+        }
+
+        summary.record(file_result(path, FileStatus::Changed));
+    }
+
+    let summary = BulkFormatSummary::from(summary);
+
+    if let Some(output_format) = args.output_format {
+        return report(&summary, output_format, args.check);
+    }
+
+    print_file_errors(&summary);
+    print_bulk_format_summary(&summary, args.check, args.diff)
+}
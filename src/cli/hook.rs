@@ -0,0 +1,205 @@
+use crate::cli::GlobalOptions;
+use crate::git::GitRepo;
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use std::fs;
+use std::path::Path;
+
+const HOOK_NAME: &str = "pre-commit";
+/// An existing hook is moved here, and run by the foro hook before formatting.
+const CHAINED_HOOK_NAME: &str = "pre-commit.foro-chained";
+const HOOK_MARKER: &str = "# Installed by `foro hook install`";
+
+/// Hooks for the pre-commit framework, published in `.pre-commit-hooks.yaml`.
+/// The framework passes the staged files (stashing unstaged changes) as arguments.
+const PRE_COMMIT_HOOKS: [PreCommitHook; 2] = [
+    PreCommitHook {
+        id: "foro",
+        name: "foro",
+        description: "Format files with foro",
+        args: "",
+    },
+    PreCommitHook {
+        id: "foro-check",
+        name: "foro (check)",
+        description: "Check that files are formatted with foro",
+        args: " --check",
+    },
+];
+
+struct PreCommitHook {
+    id: &'static str,
+    name: &'static str,
+    description: &'static str,
+    args: &'static str,
+}
+
+#[derive(Parser, Debug)]
+pub enum HookSubCommands {
+    /// Install a git pre-commit hook that formats the staged files
+    Install(HookInstallArgs),
+    /// Remove the pre-commit hook installed by `foro hook install`
+    Uninstall(HookUninstallArgs),
+    /// Print the hook definitions for the pre-commit framework (`.pre-commit-hooks.yaml`)
+    PreCommitHooks(HookPreCommitHooksArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct HookArgs {
+    #[clap(subcommand)]
+    pub subcommand: HookSubCommands,
+}
+
+#[derive(Parser, Debug)]
+pub struct HookInstallArgs {}
+
+#[derive(Parser, Debug)]
+pub struct HookUninstallArgs {}
+
+#[derive(Parser, Debug)]
+pub struct HookPreCommitHooksArgs {}
+
+pub fn hook_execute_with_args(args: HookArgs, global_options: GlobalOptions) -> Result<()> {
+    match args.subcommand {
+        HookSubCommands::Install(s_args) => hook_install_execute_with_args(s_args, global_options),
+        HookSubCommands::Uninstall(s_args) => {
+            hook_uninstall_execute_with_args(s_args, global_options)
+        }
+        HookSubCommands::PreCommitHooks(s_args) => {
+            hook_pre_commit_hooks_execute_with_args(s_args, global_options)
+        }
+    }
+}
+
+fn hook_script() -> String {
+    format!(
+        r#"#!/bin/sh
+{HOOK_MARKER}. Remove it with `foro hook uninstall`.
+
+chained="$(dirname "$0")/{CHAINED_HOOK_NAME}"
+if [ -x "$chained" ]; then
+    "$chained" "$@" || exit $?
+fi
+
+exec foro format --staged
+"#
+    )
+}
+
+fn is_foro_hook(path: &Path) -> bool {
+    fs::read(path).is_ok_and(|content| String::from_utf8_lossy(&content).contains(HOOK_MARKER))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o755);
+    fs::set_permissions(path, permissions)?;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+pub fn hook_install_execute_with_args(
+    _args: HookInstallArgs,
+    _global_options: GlobalOptions,
+) -> Result<()> {
+    let repo = GitRepo::discover(&std::env::current_dir()?)?;
+    let hooks_dir = repo.hooks_dir()?;
+    let hook = hooks_dir.join(HOOK_NAME);
+    let chained = hooks_dir.join(CHAINED_HOOK_NAME);
+
+    fs::create_dir_all(&hooks_dir)
+        .with_context(|| format!("Failed to create hooks directory: {hooks_dir:?}"))?;
+
+    if hook.exists() && !is_foro_hook(&hook) {
+        if chained.exists() {
+            bail!("Can't keep the existing hook, since {chained:?} already exists");
+        }
+
+        fs::rename(&hook, &chained)
+            .with_context(|| format!("Failed to move the existing hook to {chained:?}"))?;
+        eprintln!(
+            "Moved the existing hook to {}, it will run before foro.",
+            chained.display()
+        );
+    }
+
+    fs::write(&hook, hook_script()).with_context(|| format!("Failed to write hook: {hook:?}"))?;
+    make_executable(&hook)?;
+
+    println!("Installed pre-commit hook: {}", hook.display());
+
+    Ok(())
+}
+
+pub fn hook_uninstall_execute_with_args(
+    _args: HookUninstallArgs,
+    _global_options: GlobalOptions,
+) -> Result<()> {
+    let repo = GitRepo::discover(&std::env::current_dir()?)?;
+    let hooks_dir = repo.hooks_dir()?;
+    let hook = hooks_dir.join(HOOK_NAME);
+    let chained = hooks_dir.join(CHAINED_HOOK_NAME);
+
+    if !is_foro_hook(&hook) {
+        bail!("No pre-commit hook installed by foro in {hooks_dir:?}");
+    }
+
+    fs::remove_file(&hook).with_context(|| format!("Failed to remove hook: {hook:?}"))?;
+
+    if chained.exists() {
+        fs::rename(&chained, &hook)
+            .with_context(|| format!("Failed to restore the previous hook from {chained:?}"))?;
+        eprintln!("Restored the previous hook.");
+    }
+
+    println!("Uninstalled pre-commit hook: {}", hook.display());
+
+    Ok(())
+}
+
+fn pre_commit_hooks_yaml() -> String {
+    let mut yaml = String::from(
+        "# Generated by `foro hook pre-commit-hooks`, don't edit it by hand.\n\
+         # Hooks for https://pre-commit.com\n",
+    );
+
+    for hook in PRE_COMMIT_HOOKS {
+        yaml.push_str(&format!(
+            "- id: {}\n  name: {}\n  description: {}\n  entry: foro format{}\n  language: rust\n  types: [text]\n  require_serial: true\n",
+            hook.id, hook.name, hook.description, hook.args
+        ));
+    }
+
+    yaml
+}
+
+pub fn hook_pre_commit_hooks_execute_with_args(
+    _args: HookPreCommitHooksArgs,
+    _global_options: GlobalOptions,
+) -> Result<()> {
+    print!("{}", pre_commit_hooks_yaml());
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pre_commit_hooks_yaml_is_up_to_date() {
+        assert_eq!(
+            pre_commit_hooks_yaml(),
+            include_str!("../../.pre-commit-hooks.yaml"),
+            ".pre-commit-hooks.yaml is outdated, regenerate it with `cargo run -- hook pre-commit-hooks > .pre-commit-hooks.yaml`"
+        );
+    }
+}
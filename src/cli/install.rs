@@ -1,31 +1,91 @@
+use crate::bulk_format::default_ignore_overrides;
 use crate::cli::GlobalOptions;
-use crate::config::{load_config_and_cache, read_config_bytes};
+use crate::config::{load_config_and_cache, project_config_in, resolve_config_file};
 use crate::install_check::mark_ready;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
 use dll_pack::resolve::download;
 use dll_pack::resolve::ResolveError;
 use dll_pack::THIS_PLATFORM;
-use std::collections::HashSet;
+use ignore::WalkBuilder;
+use log::{info, warn};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
 use url::Url;
 
 #[derive(Parser, Debug)]
-pub struct InstallArgs {}
+pub struct InstallArgs {
+    /// Also install the plugins of the project configs used for files under these paths
+    /// (e.g. the sub-projects of a monorepo)
+    pub paths: Vec<PathBuf>,
+}
+
+/// The project configs in `path` and the directories under it, skipping ignored directories
+/// like `foro format <dir>` does.
+fn project_configs_under(path: &Path) -> Vec<PathBuf> {
+    let mut walk_builder = WalkBuilder::new(path);
+    walk_builder
+        .add_custom_ignore_filename(".foro-ignore")
+        .overrides(default_ignore_overrides(path))
+        .filter_entry(|entry| entry.file_type().is_some_and(|t| t.is_dir()));
+
+    walk_builder
+        .build()
+        .filter_map(|entry| match entry {
+            Ok(entry) => project_config_in(entry.path()),
+            Err(err) => {
+                warn!("Failed to walk {path:?}: {err}");
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn install_execute_with_args(args: InstallArgs, global_options: GlobalOptions) -> Result<()> {
+    // `None` is the global config
+    let mut config_files = BTreeSet::from([global_options.project_config_file()]);
+
+    for path in &args.paths {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {path:?}"))?;
+        let dir = if path.is_dir() {
+            path.as_path()
+        } else {
+            path.parent().unwrap_or(&path)
+        };
+
+        config_files.insert(resolve_config_file(
+            global_options.config_file.as_deref(),
+            dir,
+        ));
 
-pub fn install_execute_with_args(_args: InstallArgs, global_options: GlobalOptions) -> Result<()> {
-    let config_bytes = read_config_bytes(global_options.config_file.as_deref())?;
-    let (config, cache_dir) = load_config_and_cache(
-        global_options.config_file.as_deref(),
-        global_options.cache_dir.as_deref(),
-    )?;
+        // A given config file is used for every file
+        if global_options.config_file.is_none() {
+            config_files.extend(project_configs_under(dir).into_iter().map(Some));
+        }
+    }
+
+    let mut downloaded = HashSet::new();
+
+    for config_file in &config_files {
+        let (config, cache_dir) =
+            load_config_and_cache(config_file.as_deref(), global_options.cache_dir.as_deref())?;
 
-    let urls: HashSet<Url> = config.all_plugin_urls().into_iter().collect();
+        for url in config.all_plugin_urls() {
+            if downloaded.insert((url.clone(), cache_dir.clone())) {
+                download_with_wasm_fallback(&url, &cache_dir)?;
+            }
+        }
 
-    for url in &urls {
-        download_with_wasm_fallback(url, &cache_dir)?;
+        mark_ready(&config, &cache_dir)?;
+
+        match config_file {
+            Some(config_file) => info!("Installed plugins for {config_file:?}"),
+            None => info!("Installed plugins for the global config"),
+        }
     }
 
-    mark_ready(&config_bytes, &cache_dir)?;
     Ok(())
 }
 
@@ -0,0 +1,25 @@
+use crate::cli::GlobalOptions;
+use crate::config::load_paths;
+use crate::daemon::client::ensure_daemon_running;
+use crate::daemon::interface::{DaemonExecutionOptions, DaemonSocketPath};
+use crate::lsp::run_lsp_server;
+use anyhow::Result;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+pub struct LspArgs {}
+
+pub fn lsp_execute_with_args(_args: LspArgs, global_options: GlobalOptions) -> Result<()> {
+    let (_, _, socket_dir) = load_paths(
+        global_options.project_config_file().as_deref(),
+        global_options.cache_dir.as_deref(),
+        global_options.socket_dir.as_deref(),
+    )?;
+
+    let socket = DaemonSocketPath::from_socket_dir(&socket_dir);
+    let daemon_options = DaemonExecutionOptions::from(&global_options);
+
+    ensure_daemon_running(&socket, &daemon_options)?;
+
+    run_lsp_server(socket, daemon_options)
+}
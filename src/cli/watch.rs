@@ -0,0 +1,49 @@
+use crate::cli::GlobalOptions;
+use crate::config::load_paths;
+use crate::daemon::client::{ensure_daemon_running, run_command as daemon_run_command};
+use crate::daemon::interface::{
+    DaemonCommands, DaemonExecutionOptions, DaemonSocketPath, DaemonWatchArgs,
+};
+use anyhow::Result;
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct WatchArgs {
+    /// Paths to watch. Files in them are formatted by the daemon when they are saved.
+    #[clap(default_value = ".")]
+    pub paths: Vec<PathBuf>,
+
+    /// Stop watching the given paths
+    #[clap(long)]
+    pub stop: bool,
+
+    /// Only list the watched paths
+    #[clap(long, conflicts_with_all = ["paths", "stop"])]
+    pub list: bool,
+}
+
+pub fn watch_execute_with_args(args: WatchArgs, global_options: GlobalOptions) -> Result<()> {
+    let (_, _, socket_dir) = load_paths(
+        global_options.project_config_file().as_deref(),
+        global_options.cache_dir.as_deref(),
+        global_options.socket_dir.as_deref(),
+    )?;
+
+    let socket = DaemonSocketPath::from_socket_dir(&socket_dir);
+    let daemon_options = DaemonExecutionOptions::from(&global_options);
+
+    ensure_daemon_running(&socket, &daemon_options)?;
+
+    let paths = if args.list { Vec::new() } else { args.paths };
+
+    daemon_run_command(
+        DaemonCommands::Watch(DaemonWatchArgs {
+            paths,
+            stop: args.stop,
+        }),
+        daemon_options,
+        &socket,
+        true,
+    )
+}
@@ -1,5 +1,7 @@
 mod load;
 mod model;
+mod validate;
 
 pub(crate) use load::*;
 pub use model::*;
+pub(crate) use validate::*;
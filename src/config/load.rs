@@ -8,6 +8,39 @@ use std::borrow::Cow;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+/// File names of per-project config files, in order of precedence.
+pub(crate) const PROJECT_CONFIG_FILE_NAMES: [&str; 4] =
+    ["foro.json", ".foro.json", "foro.toml", ".foro.toml"];
+
+// project config discovery
+// ----------------------------------------
+
+/// The project config file in `dir` itself, if any.
+pub(crate) fn project_config_in(dir: &Path) -> Option<PathBuf> {
+    PROJECT_CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Find the nearest project config file in `dir` or its ancestors.
+pub(crate) fn find_project_config(dir: &Path) -> Option<PathBuf> {
+    let candidate = dir.ancestors().find_map(project_config_in)?;
+    debug!("found project config file: {:?}", candidate);
+    Some(candidate)
+}
+
+/// Resolve the config file to use for files under `dir`.
+///
+/// A given config file (i.e. `--config-file`) always takes precedence.
+/// Otherwise, the nearest project config is used.
+/// `None` means that the global config file should be used.
+pub(crate) fn resolve_config_file(given_config_file: Option<&Path>, dir: &Path) -> Option<PathBuf> {
+    given_config_file
+        .map(Path::to_path_buf)
+        .or_else(|| find_project_config(dir))
+}
+
 // functions that manually inject resolvers
 // ----------------------------------------
 
@@ -51,11 +84,7 @@ pub(crate) fn load_config_and_cache_with<R: AppDirResolver>(
     let config = load_file(&config_file)
         .with_context(|| format!("Failed to load config file ({:?})", &config_file))?;
 
-    let cache_dir = given_cache_dir
-        .map(Path::to_path_buf)
-        .or(config.cache_dir.clone())
-        .or_else(|| resolver.cache_dir())
-        .context("Failed to get cache directory")?;
+    let cache_dir = cache_dir_for_with(resolver, &config, given_cache_dir)?;
 
     debug!("config file: {:?}", &config_file);
     debug_long!("config: {:?}", &config);
@@ -64,6 +93,19 @@ pub(crate) fn load_config_and_cache_with<R: AppDirResolver>(
     Ok((config, cache_dir))
 }
 
+/// The cache directory to use: the given one, or the config's, or the default.
+pub(crate) fn cache_dir_for_with<R: AppDirResolver>(
+    resolver: &R,
+    config: &Config,
+    given_cache_dir: Option<&Path>,
+) -> Result<PathBuf> {
+    given_cache_dir
+        .map(Path::to_path_buf)
+        .or(config.cache_dir.clone())
+        .or_else(|| resolver.cache_dir())
+        .context("Failed to get cache directory")
+}
+
 pub(crate) fn load_config_and_socket_with<R: AppDirResolver>(
     resolver: &R,
     given_config_file: Option<&Path>,
@@ -112,11 +154,7 @@ pub(crate) fn load_paths_with<R: AppDirResolver>(
 
     let config_file = config_file.to_path_buf();
 
-    let cache_dir = given_cache_dir
-        .map(Path::to_path_buf)
-        .or(config.cache_dir.clone())
-        .or_else(|| resolver.cache_dir())
-        .context("Failed to get cache directory")?;
+    let cache_dir = cache_dir_for_with(resolver, &config, given_cache_dir)?;
 
     let socket_dir = given_socket_dir
         .map(Path::to_path_buf)
@@ -134,13 +172,8 @@ pub(crate) fn get_or_create_default_config() -> Option<PathBuf> {
     get_or_create_default_config_with(&DefaultAppDirResolver {})
 }
 
-pub(crate) fn read_config_bytes(given_config_file: Option<&Path>) -> Result<Vec<u8>> {
-    let config_file = match given_config_file {
-        Some(p) => p.to_path_buf(),
-        None => get_or_create_default_config().context("Failed to get config directory")?,
-    };
-    fs::read(&config_file)
-        .with_context(|| format!("Failed to read config file ({:?})", &config_file))
+pub(crate) fn cache_dir_for(config: &Config, given_cache_dir: Option<&Path>) -> Result<PathBuf> {
+    cache_dir_for_with(&DefaultAppDirResolver {}, config, given_cache_dir)
 }
 
 pub(crate) fn load_config_and_cache(
@@ -438,6 +471,68 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_find_project_config_walks_up_to_nearest() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let sub = root.join("sub");
+        let nested = sub.join("a").join("b");
+        fs::create_dir_all(&nested)?;
+
+        assert_eq!(find_project_config(&nested), None);
+
+        fs::write(root.join("foro.json"), r#"{"rules":[]}"#)?;
+        assert_eq!(find_project_config(&nested), Some(root.join("foro.json")));
+
+        fs::write(sub.join(".foro.json"), r#"{"rules":[]}"#)?;
+        assert_eq!(find_project_config(&nested), Some(sub.join(".foro.json")));
+
+        // `foro.json` takes precedence over `.foro.json` in the same directory.
+        fs::write(sub.join("foro.json"), r#"{"rules":[]}"#)?;
+        assert_eq!(find_project_config(&nested), Some(sub.join("foro.json")));
+        assert_eq!(find_project_config(root), Some(root.join("foro.json")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_project_config_toml() -> Result<()> {
+        let temp_dir = tempdir()?;
+        fs::write(temp_dir.path().join("foro.toml"), "rules = []")?;
+
+        assert_eq!(
+            find_project_config(temp_dir.path()),
+            Some(temp_dir.path().join("foro.toml"))
+        );
+
+        // A JSON config takes precedence over a TOML config in the same directory.
+        fs::write(temp_dir.path().join(".foro.json"), r#"{"rules":[]}"#)?;
+        assert_eq!(
+            find_project_config(temp_dir.path()),
+            Some(temp_dir.path().join(".foro.json"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_config_file_prefers_given_config() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let given = temp_dir.path().join("given.json");
+        fs::write(temp_dir.path().join("foro.json"), r#"{"rules":[]}"#)?;
+
+        assert_eq!(
+            resolve_config_file(Some(&given), temp_dir.path()),
+            Some(given)
+        );
+        assert_eq!(
+            resolve_config_file(None, temp_dir.path()),
+            Some(temp_dir.path().join("foro.json"))
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_get_or_create_default_config() -> Result<()> {
         let temp_dir = tempdir()?;
@@ -1,77 +1,471 @@
-use anyhow::{anyhow, Context};
-use serde::{Deserialize, Serialize};
+use crate::app_dir::{AppDirResolver, DefaultAppDirResolver};
+use anyhow::{anyhow, bail, Context};
+use globset::{GlobBuilder, GlobMatcher};
+use log::{debug, warn};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Metadata, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::OnceCell;
 use std::collections::HashMap;
 use std::fs;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use url::Url;
+use xxhash_rust::xxh3::xxh3_128;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// A compiled glob pattern, (de)serialized as its source string.
+///
+/// `*` doesn't match `/`, use `**` to match across directories.
+#[derive(Debug, Clone)]
+pub struct GlobPattern {
+    pattern: String,
+    matcher: GlobMatcher,
+}
+
+impl GlobPattern {
+    pub fn new(pattern: &str) -> Result<Self, globset::Error> {
+        let matcher = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()?
+            .compile_matcher();
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            matcher,
+        })
+    }
+
+    pub fn is_match(&self, path: &Path) -> bool {
+        self.matcher.is_match(path)
+    }
+}
+
+impl PartialEq for GlobPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Serialize for GlobPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+impl JsonSchema for GlobPattern {
+    fn schema_name() -> String {
+        "GlobPattern".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        string_schema("A glob pattern, where `*` doesn't match `/`")
+    }
+}
+
+impl<'de> Deserialize<'de> for GlobPattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        GlobPattern::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A compiled regex matched against bytes, (de)serialized as its source string.
+#[derive(Debug, Clone)]
+pub struct RegexPattern(regex::bytes::Regex);
+
+impl RegexPattern {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        regex::bytes::Regex::new(pattern).map(Self)
+    }
+
+    pub fn is_match(&self, bytes: &[u8]) -> bool {
+        self.0.is_match(bytes)
+    }
+}
+
+impl PartialEq for RegexPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Serialize for RegexPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl JsonSchema for RegexPattern {
+    fn schema_name() -> String {
+        "RegexPattern".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = string_schema("A regular expression");
+        if let Schema::Object(object) = &mut schema {
+            object.format = Some("regex".to_string());
+        }
+        schema
+    }
+}
+
+impl<'de> Deserialize<'de> for RegexPattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        RegexPattern::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+fn string_schema(description: &str) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        metadata: Some(Box::new(Metadata {
+            description: Some(description.to_string()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Number of leading bytes of a file that content-based rules look at.
+pub const FILE_HEAD_LEN: usize = 4096;
+
+enum FileHeadSource<'a> {
+    Content(&'a [u8]),
+    File(&'a Path),
+}
+
+/// The leading bytes of a file, which are only read when a content-based rule needs them.
+pub struct FileHead<'a> {
+    source: FileHeadSource<'a>,
+    head: OnceCell<Vec<u8>>,
+}
+
+impl<'a> FileHead<'a> {
+    /// Use content that is already in memory.
+    pub fn from_content(content: &'a str) -> Self {
+        Self {
+            source: FileHeadSource::Content(content.as_bytes()),
+            head: OnceCell::new(),
+        }
+    }
+
+    /// Read the file on first access. A file that can't be read is treated as empty.
+    pub fn from_file(path: &'a Path) -> Self {
+        Self {
+            source: FileHeadSource::File(path),
+            head: OnceCell::new(),
+        }
+    }
+
+    pub fn get(&self) -> &[u8] {
+        match self.source {
+            FileHeadSource::Content(content) => &content[..content.len().min(FILE_HEAD_LEN)],
+            FileHeadSource::File(path) => self.head.get_or_init(|| {
+                let mut head = Vec::new();
+                let res = fs::File::open(path)
+                    .and_then(|file| file.take(FILE_HEAD_LEN as u64).read_to_end(&mut head));
+
+                match res {
+                    Ok(_) => head,
+                    Err(_) => Vec::new(),
+                }
+            }),
+        }
+    }
+}
+
+impl Default for FileHead<'_> {
+    fn default() -> Self {
+        Self::from_content("")
+    }
+}
+
+/// Get the interpreter name of a shebang line, e.g. `python3` for `#!/usr/bin/env python3`.
+fn shebang_interpreter(head: &[u8]) -> Option<&str> {
+    let line = head.strip_prefix(b"#!")?.split(|&b| b == b'\n').next()?;
+    let mut words = std::str::from_utf8(line).ok()?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+
+    if program == "env" {
+        // skip options and variable assignments, e.g. `#!/usr/bin/env -S FOO=1 node`
+        words
+            .find(|word| !word.starts_with('-') && !word.contains('='))?
+            .rsplit('/')
+            .next()
+    } else {
+        Some(program)
+    }
+}
+
+/// Object keys of the `OnRule` variants, which must be used alone.
+const ON_RULE_KEYS: [&str; 7] = [
+    "glob", "filename", "suffix", "shebang", "content", "all", "not",
+];
+
+// Deserialize is implemented by hand instead of `#[serde(untagged)]`, since untagged
+// struct variants silently accept unknown keys (e.g. `{"glob": "*.ts", "not": ".d.ts"}`).
+#[derive(Serialize, JsonSchema, Debug, Clone, PartialEq)]
 #[serde(untagged)]
+#[schemars(deny_unknown_fields)]
 pub enum OnRule {
+    /// Matches the last extension, e.g. `".ts"`
     Extension(String),
     Or(Vec<OnRule>),
+    /// Matches the path relative to the config root (or, for files outside it, e.g. with the
+    /// global config, relative to their repository), e.g. `{"glob": "src/**/*.gen.ts"}`
+    Glob {
+        glob: GlobPattern,
+    },
+    /// Matches the exact file name, e.g. `{"filename": "Dockerfile"}`
+    Filename {
+        filename: String,
+    },
+    /// Matches the end of the file name, e.g. `{"suffix": ".d.ts"}`
+    Suffix {
+        suffix: String,
+    },
+    /// Matches the interpreter of the shebang line, e.g. `{"shebang": "python3"}`
+    Shebang {
+        shebang: String,
+    },
+    /// Matches a regex against the leading bytes of the content, e.g. `{"content": "^<\\?php"}`
+    Content {
+        content: RegexPattern,
+    },
+    /// Matches if all rules match, e.g. `{"all": [".ts", {"not": {"glob": "vendor/**"}}]}`
+    All {
+        all: Vec<OnRule>,
+    },
+    /// Matches if the rule doesn't match, e.g. `{"not": {"filename": "package-lock.json"}}`
+    Not {
+        not: Box<OnRule>,
+    },
 }
 
 impl OnRule {
-    pub fn on_match(&self, target_path: &Path) -> bool {
+    fn from_value(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        use serde::de::Error;
+        use serde_json::{from_value, Value};
+
+        match value {
+            Value::String(ext) => Ok(OnRule::Extension(ext)),
+            Value::Array(_) => Ok(OnRule::Or(from_value(value)?)),
+            Value::Object(map) => {
+                let mut entries = map.into_iter();
+
+                let (key, inner) = match (entries.next(), entries.next()) {
+                    (Some(entry), None) => entry,
+                    _ => {
+                        return Err(Error::custom(format!(
+                            "invalid `on` rule: an object must have exactly one of the keys {ON_RULE_KEYS:?}"
+                        )))
+                    }
+                };
+
+                match key.as_str() {
+                    "glob" => Ok(OnRule::Glob {
+                        glob: from_value(inner)?,
+                    }),
+                    "filename" => Ok(OnRule::Filename {
+                        filename: from_value(inner)?,
+                    }),
+                    "suffix" => Ok(OnRule::Suffix {
+                        suffix: from_value(inner)?,
+                    }),
+                    "shebang" => Ok(OnRule::Shebang {
+                        shebang: from_value(inner)?,
+                    }),
+                    "content" => Ok(OnRule::Content {
+                        content: from_value(inner)?,
+                    }),
+                    "all" => Ok(OnRule::All {
+                        all: from_value(inner)?,
+                    }),
+                    "not" => Ok(OnRule::Not {
+                        not: Box::new(from_value(inner)?),
+                    }),
+                    _ => Err(Error::unknown_field(&key, &ON_RULE_KEYS)),
+                }
+            }
+            other => Err(Error::custom(format!(
+                "invalid `on` rule: expected a string, an array or an object, got `{other}`"
+            ))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for OnRule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        OnRule::from_value(value).map_err(serde::de::Error::custom)
+    }
+}
+
+impl OnRule {
+    /// Check if the rule matches `target_path`, which is relative to the config root
+    /// (or its repository) if the target is under it. `head` is only read by content-based rules.
+    pub fn on_match(&self, target_path: &Path, head: &FileHead) -> bool {
         match self {
             OnRule::Extension(ext) => target_path
                 .extension()
                 .is_some_and(|e| &format!(".{}", e.to_string_lossy()) == ext),
-            OnRule::Or(rules) => rules.iter().any(|rule| rule.on_match(target_path)),
+            OnRule::Or(rules) => rules.iter().any(|rule| rule.on_match(target_path, head)),
+            OnRule::Glob { glob } => glob.is_match(target_path),
+            OnRule::Filename { filename } => target_path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy() == filename.as_str()),
+            OnRule::Suffix { suffix } => target_path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().ends_with(suffix.as_str())),
+            OnRule::Shebang { shebang } => {
+                shebang_interpreter(head.get()).is_some_and(|name| name == shebang)
+            }
+            OnRule::Content { content } => content.is_match(head.get()),
+            OnRule::All { all } => all.iter().all(|rule| rule.on_match(target_path, head)),
+            OnRule::Not { not } => !not.on_match(target_path, head),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(untagged)]
 pub enum Command {
-    PluginUrl(#[serde(with = "url_serde")] Url),
+    /// The URL of a plugin (`.dllpack`)
+    PluginUrl(
+        #[serde(with = "url_serde")]
+        #[schemars(with = "String", url)]
+        Url,
+    ),
+    /// A shell command that reads the content from stdin and writes the result to stdout
     CommandIO { io: String },
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde(untagged)]
 pub enum CommandWithControlFlow<T> {
+    /// Runs `run`, then `on_true` or `on_false` depending on the expression `cond`
     If {
         run: Box<CommandWithControlFlow<T>>,
         cond: String,
         on_true: Box<CommandWithControlFlow<T>>,
         on_false: Box<CommandWithControlFlow<T>>,
     },
+    /// Runs the commands in order
     Sequential(Vec<CommandWithControlFlow<T>>),
+    /// Sets variables from expressions
     Set {
         set: HashMap<String, String>,
     },
     Command(T),
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Which of the matching rules are run.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleMode {
+    /// Stop at the first matching rule.
+    #[default]
+    First,
+    /// Keep going, so that later matching rules also run on the output.
+    All,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Rule {
     pub on: OnRule,
     pub cmd: CommandWithControlFlow<Command>,
+    /// Overrides the config's `mode` for this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<RuleMode>,
+    /// Settings of the cached results of this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<RuleCache>,
+}
+
+/// Settings of the cached results of a rule.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleCache {
+    /// Names of other files the formatter reads its settings from, in addition to the known
+    /// config files of the formatter (e.g. `rustfmt.toml`), looked up in the directory of
+    /// the target and its ancestors. A change to any of them invalidates the cached results.
+    #[serde(default)]
+    pub inputs: Vec<String>,
 }
 
 impl Rule {
-    pub fn on_match(&self, target_path: &Path) -> bool {
-        self.on.on_match(target_path)
+    pub fn on_match(&self, target_path: &Path, head: &FileHead) -> bool {
+        self.on.on_match(target_path, head)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// How the rules of a config are merged with the rules of the config it extends.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeRules {
+    /// Own rules come before the base rules, so they take priority.
+    #[default]
+    Prepend,
+    /// Own rules come after the base rules, so they only fill the gaps.
+    Append,
+    /// Own rules replace the base rules with the same `on` in place,
+    /// and the rest are prepended.
+    Override,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Config {
+    /// A base config to inherit from, as a local path (relative to this config) or a URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub merge_rules: MergeRules,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<RuleMode>,
+    /// Rules to find the formatter of a file, tried in order.
     pub rules: Vec<Rule>,
-    #[serde(default = "none")]
+    /// Where plugins are downloaded to. Defaults to the user cache directory.
+    #[serde(default = "none::<PathBuf>")]
     pub cache_dir: Option<PathBuf>,
-    #[serde(default = "none")]
+    /// Where the daemon socket is created. Defaults to the user runtime directory.
+    #[serde(default = "none::<PathBuf>")]
     pub socket_dir: Option<PathBuf>,
+    /// The directory of the config file, which path-based rules are relative to.
+    #[serde(skip)]
+    pub root: Option<PathBuf>,
+}
+
+/// The JSON Schema of [`Config`], which `foro.schema.json` is generated from.
+pub fn config_schema() -> String {
+    let schema = schemars::schema_for!(Config);
+    serde_json::to_string_pretty(&schema).expect("a schema is always serializable")
 }
 
 fn none<T>() -> Option<T> {
     None
 }
 
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    value == &T::default()
+}
+
+/// The repository `target_path` is in, i.e. its nearest ancestor with a `.git`.
+fn repository_root(target_path: &Path) -> Option<&Path> {
+    // A relative path would be looked up from the current directory
+    if !target_path.is_absolute() {
+        return None;
+    }
+
+    target_path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join(".git").exists())
+}
+
 fn collect_urls(cmd: &CommandWithControlFlow<Command>, urls: &mut Vec<Url>) {
     match cmd {
         CommandWithControlFlow::Command(Command::PluginUrl(url)) => urls.push(url.clone()),
@@ -96,14 +490,37 @@ fn collect_urls(cmd: &CommandWithControlFlow<Command>, urls: &mut Vec<Url>) {
 }
 
 impl Config {
-    pub fn find_matched_rule(&self, target_path: &Path) -> Option<Rule> {
+    /// Find the rules to run for `target_path`, in order. `head` gives the leading bytes
+    /// of the file, which are only read if a content-based rule has to be checked.
+    ///
+    /// Path-based rules match the path relative to the config root. Targets outside it
+    /// (e.g. with the global config) are matched relative to their repository instead.
+    ///
+    /// Matching stops at the first matching rule, unless its `mode` (or the config's) is `all`.
+    pub fn find_matched_rules(&self, target_path: &Path, head: &FileHead) -> Vec<Rule> {
+        let target_path = self
+            .root
+            .as_deref()
+            .and_then(|root| target_path.strip_prefix(root).ok())
+            .or_else(|| {
+                repository_root(target_path).and_then(|root| target_path.strip_prefix(root).ok())
+            })
+            .unwrap_or(target_path);
+        let mode = self.mode.unwrap_or_default();
+
+        let mut matched = Vec::new();
+
         for rule in &self.rules {
-            if rule.on_match(target_path) {
-                return Some(rule.clone());
+            if rule.on_match(target_path, head) {
+                matched.push(rule.clone());
+
+                if rule.mode.unwrap_or(mode) == RuleMode::First {
+                    break;
+                }
             }
         }
 
-        None
+        matched
     }
 
     pub fn all_plugin_urls(&self) -> Vec<Url> {
@@ -113,19 +530,299 @@ impl Config {
         }
         urls
     }
+
+    /// Merge this config onto the `base` config it extends.
+    fn merge_onto(self, base: Config) -> Config {
+        let rules = match self.merge_rules {
+            MergeRules::Prepend => self.rules.into_iter().chain(base.rules).collect(),
+            MergeRules::Append => base.rules.into_iter().chain(self.rules).collect(),
+            MergeRules::Override => {
+                let mut rules = base.rules;
+                let mut prepended = Vec::new();
+
+                for rule in self.rules {
+                    match rules.iter_mut().find(|base_rule| base_rule.on == rule.on) {
+                        Some(base_rule) => *base_rule = rule,
+                        None => prepended.push(rule),
+                    }
+                }
+
+                prepended.into_iter().chain(rules).collect()
+            }
+        };
+
+        Config {
+            extends: None,
+            merge_rules: MergeRules::default(),
+            mode: self.mode.or(base.mode),
+            rules,
+            cache_dir: self.cache_dir.or(base.cache_dir),
+            socket_dir: self.socket_dir.or(base.socket_dir),
+            root: None,
+        }
+    }
+}
+
+/// The syntax of a config file, decided by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// JSON, also accepting JSONC/JSON5 extensions like comments and trailing commas.
+    Json,
+    Toml,
 }
 
+impl ConfigFormat {
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".toml") {
+            ConfigFormat::Toml
+        } else {
+            ConfigFormat::Json
+        }
+    }
+
+    /// Parse a config (or its raw value) without resolving `extends`.
+    /// Errors report the line and column.
+    pub fn parse<T: DeserializeOwned>(self, bytes: &[u8]) -> anyhow::Result<T> {
+        match self {
+            ConfigFormat::Json => parse_json(bytes),
+            ConfigFormat::Toml => {
+                let text = std::str::from_utf8(bytes).context("Config is not valid UTF-8")?;
+                toml::from_str(text).map_err(|e| anyhow!(e))
+            }
+        }
+    }
+}
+
+fn parse_json<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T> {
+    // Plain JSON is parsed with serde_json first, since it's much faster than json5,
+    // and only falls back to json5 on syntax errors (e.g. comments or trailing commas)
+    match serde_json::from_slice(bytes) {
+        Ok(config) => Ok(config),
+        Err(err) if err.classify() == serde_json::error::Category::Syntax => {
+            let text = std::str::from_utf8(bytes).context("Config is not valid UTF-8")?;
+
+            json5::from_str(text).map_err(|err| match err {
+                json5::Error::Message {
+                    msg,
+                    location: Some(location),
+                } => anyhow!("{msg} at line {} column {}", location.line, location.column),
+                json5::Error::Message {
+                    msg,
+                    location: None,
+                } => anyhow!(msg),
+            })
+        }
+        Err(err) => Err(anyhow!(err)),
+    }
+}
+
+/// Where a config was loaded from, used to resolve relative `extends` and detect cycles.
+#[derive(Debug, Clone, PartialEq)]
+enum ConfigSource {
+    File(PathBuf),
+    Url(Url),
+}
+
+impl ConfigSource {
+    fn resolve(origin: Option<&ConfigSource>, extends: &str) -> anyhow::Result<ConfigSource> {
+        if let Ok(url) = Url::parse(extends) {
+            if matches!(url.scheme(), "http" | "https") {
+                return Ok(ConfigSource::Url(url));
+            }
+        }
+
+        match origin {
+            Some(ConfigSource::Url(url)) => {
+                Ok(ConfigSource::Url(url.join(extends).with_context(|| {
+                    format!("Invalid extends ({extends:?}) in {url}")
+                })?))
+            }
+            Some(ConfigSource::File(path)) => Ok(ConfigSource::File(
+                path.parent().unwrap_or(Path::new(".")).join(extends),
+            )),
+            None => Ok(ConfigSource::File(PathBuf::from(extends))),
+        }
+    }
+
+    /// Identity of the source, used for cycle detection.
+    fn key(&self) -> String {
+        match self {
+            ConfigSource::File(path) => fs::canonicalize(path)
+                .unwrap_or_else(|_| path.clone())
+                .display()
+                .to_string(),
+            ConfigSource::Url(url) => url.to_string(),
+        }
+    }
+
+    fn format(&self) -> ConfigFormat {
+        match self {
+            ConfigSource::File(path) => ConfigFormat::from_path(&path.to_string_lossy()),
+            ConfigSource::Url(url) => ConfigFormat::from_path(url.path()),
+        }
+    }
+
+    fn load(&self) -> anyhow::Result<Config> {
+        self.format().parse(&self.read()?)
+    }
+
+    fn read(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            ConfigSource::File(path) => {
+                // memo: in my measurement, this implementation is faster than serde_json::from_reader, etc
+                let mut file = fs::File::open(path).context("Failed to open file")?;
+                let mut buffer = Vec::new();
+                file.read_to_end(&mut buffer)?;
+                Ok(buffer)
+            }
+            ConfigSource::Url(url) => {
+                read_url_config(url, DefaultAppDirResolver {}.cache_dir().as_deref())
+            }
+        }
+    }
+}
+
+/// How long a config fetched from a URL is used before it is fetched again.
+const URL_CONFIG_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+const URL_CONFIG_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn fetch_url_config(url: &Url) -> anyhow::Result<Vec<u8>> {
+    let response = reqwest::blocking::Client::builder()
+        .timeout(URL_CONFIG_TIMEOUT)
+        .build()?
+        .get(url.as_str())
+        .send()
+        .and_then(|res| res.error_for_status())
+        .with_context(|| format!("Failed to fetch config ({url})"))?;
+    Ok(response.bytes()?.to_vec())
+}
+
+/// Read a config from a URL, through a copy in `cache_dir` keyed by the URL.
+///
+/// A fresh copy is used without fetching. A stale one is refreshed, and used as is
+/// if the config can't be fetched (e.g. offline).
+fn read_url_config(url: &Url, cache_dir: Option<&Path>) -> anyhow::Result<Vec<u8>> {
+    let Some(cache_dir) = cache_dir else {
+        return fetch_url_config(url);
+    };
+    let cached = cache_dir
+        .join("configs")
+        .join(format!("{:032x}", xxh3_128(url.as_str().as_bytes())));
+
+    let is_fresh = fs::metadata(&cached)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age < URL_CONFIG_MAX_AGE));
+    if is_fresh {
+        if let Ok(content) = fs::read(&cached) {
+            debug!("Using cached config of {url}");
+            return Ok(content);
+        }
+    }
+
+    match fetch_url_config(url) {
+        Ok(content) => {
+            let res = fs::create_dir_all(cached.parent().unwrap())
+                .and_then(|_| fs::write(&cached, &content));
+            if let Err(err) = res {
+                warn!("Failed to cache config of {url}: {err}");
+            }
+            Ok(content)
+        }
+        Err(err) => match fs::read(&cached) {
+            Ok(content) => {
+                warn!("{err:#}, using the cached copy");
+                Ok(content)
+            }
+            Err(_) => Err(err),
+        },
+    }
+}
+
+/// Resolve the relative `cache_dir` and `socket_dir` of an extended config against its
+/// location, as they would otherwise be taken relative to the extending config's current directory.
+fn resolve_base_dirs(base: &mut Config, source: &ConfigSource) -> anyhow::Result<()> {
+    for dir in [&mut base.cache_dir, &mut base.socket_dir]
+        .into_iter()
+        .flatten()
+        .filter(|dir| !dir.has_root())
+    {
+        match source {
+            ConfigSource::File(path) => *dir = path.parent().unwrap_or(Path::new(".")).join(&*dir),
+            ConfigSource::Url(url) => {
+                bail!("Relative directory {dir:?} in a config extended from a URL ({url})")
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolve the `extends` chain of `config`, which was loaded from `origin`.
+///
+/// `chain` holds the keys of the configs being resolved, to detect cycles.
+/// Local files that are read are added to `files`.
+fn resolve_extends(
+    config: Config,
+    origin: Option<&ConfigSource>,
+    chain: &mut Vec<String>,
+    files: &mut Vec<PathBuf>,
+) -> anyhow::Result<Config> {
+    let Some(extends) = &config.extends else {
+        return Ok(config);
+    };
+
+    let source = ConfigSource::resolve(origin, extends)?;
+    let key = source.key();
+
+    if chain.contains(&key) {
+        chain.push(key);
+        bail!("Config extends cycle detected: {}", chain.join(" -> "));
+    }
+
+    chain.push(key);
+
+    if let ConfigSource::File(path) = &source {
+        files.push(fs::canonicalize(path).unwrap_or_else(|_| path.clone()));
+    }
+
+    let mut base = source
+        .load()
+        .with_context(|| format!("Failed to load extended config ({source:?})"))?;
+    resolve_base_dirs(&mut base, &source)?;
+    let base = resolve_extends(base, Some(&source), chain, files)?;
+
+    chain.pop();
+
+    Ok(config.merge_onto(base))
+}
+
+/// Load a JSON (or JSONC/JSON5) config from a string.
+/// Relative `extends` are resolved from the current directory.
 #[allow(unused)]
 pub fn load_str(json: &str) -> anyhow::Result<Config> {
-    serde_json::from_str(json).map_err(|e| anyhow!(e))
+    let config = ConfigFormat::Json.parse(json.as_bytes())?;
+    resolve_extends(config, None, &mut Vec::new(), &mut Vec::new())
 }
 
+/// Load a config file and resolve its `extends` chain.
 pub fn load_file(path: &Path) -> anyhow::Result<Config> {
-    // memo: in my measurement, this implementation is faster than serde_json::from_reader, etc
-    let mut file = fs::File::open(path).context("Failed to open file")?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    serde_json::from_slice(&buffer).map_err(|e| anyhow!(e))
+    load_file_with_sources(path).map(|(config, _)| config)
+}
+
+/// Like [`load_file`], but also returns the (canonical) local files the config was built from,
+/// i.e. the file itself and the files it extends. Extended URLs are not included.
+pub fn load_file_with_sources(path: &Path) -> anyhow::Result<(Config, Vec<PathBuf>)> {
+    let source = ConfigSource::File(path.to_path_buf());
+    let canonical = fs::canonicalize(path).ok();
+    let mut files = vec![canonical.clone().unwrap_or_else(|| path.to_path_buf())];
+
+    let config = source.load()?;
+    let mut chain = vec![source.key()];
+    let mut config = resolve_extends(config, Some(&source), &mut chain, &mut files)?;
+
+    config.root = canonical.and_then(|path| path.parent().map(Path::to_path_buf));
+
+    Ok((config, files))
 }
 
 #[cfg(test)]
@@ -138,17 +835,20 @@ mod tests {
         let on_rule = OnRule::Extension(".rs".to_string());
 
         let path = Path::new("hello_world.rs");
-        assert!(on_rule.on_match(path), "Should match `.rs` extension");
+        assert!(
+            on_rule.on_match(path, &FileHead::default()),
+            "Should match `.rs` extension"
+        );
 
         let path_ts = Path::new("example.ts");
         assert!(
-            !on_rule.on_match(path_ts),
+            !on_rule.on_match(path_ts, &FileHead::default()),
             "Should not match `.ts` extension"
         );
 
         let path_no_ext = Path::new("Makefile");
         assert!(
-            !on_rule.on_match(path_no_ext),
+            !on_rule.on_match(path_no_ext, &FileHead::default()),
             "Should not match no extension path"
         );
     }
@@ -161,14 +861,20 @@ mod tests {
         ]);
 
         let path_rs = Path::new("main.rs");
-        assert!(on_rule.on_match(path_rs), "Should match an `.rs` file");
+        assert!(
+            on_rule.on_match(path_rs, &FileHead::default()),
+            "Should match an `.rs` file"
+        );
 
         let path_js = Path::new("test.js");
-        assert!(on_rule.on_match(path_js), "Should match a `.js` file");
+        assert!(
+            on_rule.on_match(path_js, &FileHead::default()),
+            "Should match a `.js` file"
+        );
 
         let path_ts = Path::new("hello.ts");
         assert!(
-            !on_rule.on_match(path_ts),
+            !on_rule.on_match(path_ts, &FileHead::default()),
             "Should not match `.ts` extension"
         );
     }
@@ -182,13 +888,21 @@ mod tests {
                     .parse()
                     .unwrap(),
             )),
+            mode: None,
+            cache: None,
         };
 
         let path_py = Path::new("script.py");
-        assert!(rule.on_match(path_py), "Should match `.py` extension");
+        assert!(
+            rule.on_match(path_py, &FileHead::default()),
+            "Should match `.py` extension"
+        );
 
         let path_rs = Path::new("lib.rs");
-        assert!(!rule.on_match(path_rs), "Should not match `.rs` extension");
+        assert!(
+            !rule.on_match(path_rs, &FileHead::default()),
+            "Should not match `.rs` extension"
+        );
     }
 
     #[test]
@@ -211,22 +925,276 @@ mod tests {
         let config: Config = serde_json::from_str(json).expect("Should parse valid JSON");
 
         let path_ts = Path::new("app.ts");
-        let matched_ts = config.find_matched_rule(path_ts);
+        let matched_ts = config.find_matched_rules(path_ts, &FileHead::default());
         assert!(
-            matched_ts.is_some(),
+            !matched_ts.is_empty(),
             "Should find a matching rule for `.ts`"
         );
 
         let path_rs = Path::new("main.rs");
-        let matched_rs = config.find_matched_rule(path_rs);
+        let matched_rs = config.find_matched_rules(path_rs, &FileHead::default());
         assert!(
-            matched_rs.is_some(),
+            !matched_rs.is_empty(),
             "Should find a matching rule for `.rs`"
         );
 
         let path_py = Path::new("script.py");
-        let matched_py = config.find_matched_rule(path_py);
-        assert!(matched_py.is_none(), "No rule should match `.py`");
+        let matched_py = config.find_matched_rules(path_py, &FileHead::default());
+        assert!(matched_py.is_empty(), "No rule should match `.py`");
+    }
+
+    #[test]
+    fn test_config_find_matched_rules_mode() {
+        let rules = r#"[
+            {"on": ".py", "cmd": {"io": "isort"}},
+            {"on": ".py", "cmd": {"io": "black"}},
+            {"on": [".py", ".pyi"], "cmd": {"io": "last"}}
+        ]"#;
+        let ios = |config: &Config, path: &str| {
+            let rules = config.find_matched_rules(Path::new(path), &FileHead::default());
+            rule_ios(&Config {
+                rules,
+                ..config.clone()
+            })
+        };
+
+        let config: Config = serde_json::from_str(&format!(r#"{{"rules": {rules}}}"#)).unwrap();
+        assert_eq!(ios(&config, "a.py"), ["isort"]);
+
+        let config: Config =
+            serde_json::from_str(&format!(r#"{{"mode": "all", "rules": {rules}}}"#)).unwrap();
+        assert_eq!(ios(&config, "a.py"), ["isort", "black", "last"]);
+        assert_eq!(ios(&config, "a.pyi"), ["last"]);
+
+        // A rule-level mode overrides the config-level mode.
+        let config: Config = serde_json::from_str(
+            r#"{"rules": [
+                {"on": ".py", "cmd": {"io": "isort"}, "mode": "all"},
+                {"on": ".py", "cmd": {"io": "black"}},
+                {"on": ".py", "cmd": {"io": "never"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(ios(&config, "a.py"), ["isort", "black"]);
+
+        let config: Config = serde_json::from_str(
+            r#"{"mode": "all", "rules": [
+                {"on": ".py", "cmd": {"io": "isort"}, "mode": "first"},
+                {"on": ".py", "cmd": {"io": "never"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(ios(&config, "a.py"), ["isort"]);
+    }
+
+    #[test]
+    fn test_on_rule_filename_and_suffix() {
+        let on_rule: OnRule = serde_json::from_str(
+            r#"[{"filename": "Dockerfile"}, {"suffix": ".d.ts"}, {"filename": "BUILD.bazel"}]"#,
+        )
+        .unwrap();
+
+        assert!(on_rule.on_match(Path::new("docker/Dockerfile"), &FileHead::default()));
+        assert!(on_rule.on_match(Path::new("BUILD.bazel"), &FileHead::default()));
+        assert!(on_rule.on_match(Path::new("types/index.d.ts"), &FileHead::default()));
+        assert!(!on_rule.on_match(Path::new("Dockerfile.dev"), &FileHead::default()));
+        assert!(!on_rule.on_match(Path::new("index.ts"), &FileHead::default()));
+        assert!(!on_rule.on_match(Path::new("d.ts/index.ts"), &FileHead::default()));
+
+        // A multi-dot extension never matches as a plain extension.
+        assert!(!OnRule::Extension(".d.ts".to_string())
+            .on_match(Path::new("index.d.ts"), &FileHead::default()));
+    }
+
+    #[test]
+    fn test_on_rule_glob() {
+        let on_rule: OnRule = serde_json::from_str(r#"{"glob": "src/**/*.gen.ts"}"#).unwrap();
+
+        assert!(on_rule.on_match(Path::new("src/a.gen.ts"), &FileHead::default()));
+        assert!(on_rule.on_match(Path::new("src/api/v1/a.gen.ts"), &FileHead::default()));
+        assert!(!on_rule.on_match(Path::new("lib/a.gen.ts"), &FileHead::default()));
+        assert!(!on_rule.on_match(Path::new("src/a.ts"), &FileHead::default()));
+
+        // `*` doesn't cross directories.
+        let on_rule: OnRule = serde_json::from_str(r#"{"glob": "*.ts"}"#).unwrap();
+        assert!(on_rule.on_match(Path::new("a.ts"), &FileHead::default()));
+        assert!(!on_rule.on_match(Path::new("src/a.ts"), &FileHead::default()));
+
+        assert!(serde_json::from_str::<OnRule>(r#"{"glob": "src/[a"}"#).is_err());
+    }
+
+    #[test]
+    fn test_on_rule_all_and_not() {
+        let on_rule: OnRule =
+            serde_json::from_str(r#"{"all": [".ts", {"not": {"glob": "vendor/**"}}]}"#).unwrap();
+
+        assert!(on_rule.on_match(Path::new("src/a.ts"), &FileHead::default()));
+        assert!(!on_rule.on_match(Path::new("vendor/lib/a.ts"), &FileHead::default()));
+        assert!(!on_rule.on_match(Path::new("src/a.js"), &FileHead::default()));
+
+        let on_rule: OnRule = serde_json::from_str(
+            r#"{"all": [".json", {"not": {"filename": "package-lock.json"}}]}"#,
+        )
+        .unwrap();
+
+        assert!(on_rule.on_match(Path::new("package.json"), &FileHead::default()));
+        assert!(!on_rule.on_match(Path::new("web/package-lock.json"), &FileHead::default()));
+
+        // An empty `all` matches everything, an empty `or` matches nothing.
+        assert!(OnRule::All { all: vec![] }.on_match(Path::new("a.rs"), &FileHead::default()));
+        assert!(!OnRule::Or(vec![]).on_match(Path::new("a.rs"), &FileHead::default()));
+    }
+
+    #[test]
+    fn test_on_rule_ambiguous_deserialize() {
+        let parse = |json: &str| serde_json::from_str::<OnRule>(json);
+
+        // Multiple keys in one object are rejected instead of picking a variant.
+        let err = parse(r#"{"glob": "*.ts", "not": ".d.ts"}"#).unwrap_err();
+        assert!(err.to_string().contains("exactly one of the keys"), "{err}");
+        assert!(parse(r#"{}"#).is_err());
+
+        // Unknown keys and wrong value types are errors.
+        let err = parse(r#"{"any": [".ts"]}"#).unwrap_err();
+        assert!(err.to_string().contains("unknown field `any`"), "{err}");
+        assert!(parse(r#"{"all": ".ts"}"#).is_err());
+        assert!(parse(r#"{"filename": ["a", "b"]}"#).is_err());
+        assert!(parse("1").is_err());
+        assert!(parse("null").is_err());
+
+        // Nested shapes keep their meaning.
+        assert_eq!(
+            parse(r#"[".ts", {"not": [".d.ts", {"all": []}]}]"#).unwrap(),
+            OnRule::Or(vec![
+                OnRule::Extension(".ts".to_string()),
+                OnRule::Not {
+                    not: Box::new(OnRule::Or(vec![
+                        OnRule::Extension(".d.ts".to_string()),
+                        OnRule::All { all: vec![] },
+                    ])),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_on_rule_serde_roundtrip() {
+        let json = r#"[".rs",{"glob":"src/**"},{"filename":"Makefile"},{"suffix":".d.ts"},{"all":[".ts",{"not":".js"}]}]"#;
+        let on_rule: OnRule = serde_json::from_str(json).unwrap();
+
+        assert_eq!(serde_json::to_string(&on_rule).unwrap(), json);
+    }
+
+    #[test]
+    fn test_shebang_interpreter() {
+        assert_eq!(shebang_interpreter(b"#!/bin/sh\necho"), Some("sh"));
+        assert_eq!(
+            shebang_interpreter(b"#!/usr/bin/env python3\n"),
+            Some("python3")
+        );
+        assert_eq!(
+            shebang_interpreter(b"#! /usr/bin/env -S FOO=1 node --flag\r\n"),
+            Some("node")
+        );
+        assert_eq!(shebang_interpreter(b"#!/usr/bin/env"), None);
+        assert_eq!(shebang_interpreter(b"print('no shebang')"), None);
+        assert_eq!(shebang_interpreter(b""), None);
+    }
+
+    #[test]
+    fn test_on_rule_shebang_and_content() {
+        let on_rule: OnRule =
+            serde_json::from_str(r#"[{"shebang": "python3"}, {"content": "^<\\?php"}]"#).unwrap();
+        let path = Path::new("bin/deploy");
+
+        assert!(on_rule.on_match(path, &FileHead::from_content("#!/usr/bin/env python3\n")));
+        assert!(on_rule.on_match(path, &FileHead::from_content("<?php echo 1;")));
+        assert!(!on_rule.on_match(path, &FileHead::from_content("#!/bin/bash\n")));
+        assert!(!on_rule.on_match(path, &FileHead::default()));
+
+        assert!(serde_json::from_str::<OnRule>(r#"{"content": "("}"#).is_err());
+    }
+
+    #[test]
+    fn test_file_head_is_read_lazily() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let script = temp_dir.path().join("deploy");
+        fs::write(&script, "#!/usr/bin/env python3\nprint(1)\n").unwrap();
+
+        let config: Config = serde_json::from_str(
+            r#"{"rules": [
+                {"on": ".rs", "cmd": {"io": "rs"}},
+                {"on": {"shebang": "python3"}, "cmd": {"io": "py"}}
+            ]}"#,
+        )
+        .unwrap();
+
+        let head = FileHead::from_file(&script);
+        assert!(!config.find_matched_rules(&script, &head).is_empty());
+        assert!(
+            head.head.get().is_some(),
+            "Content rules should read the file"
+        );
+
+        let rs_file = temp_dir.path().join("main.rs");
+        let head = FileHead::from_file(&rs_file);
+        assert!(!config.find_matched_rules(&rs_file, &head).is_empty());
+        assert!(
+            head.head.get().is_none(),
+            "The file shouldn't be read if no content rule is checked"
+        );
+
+        // A missing file is treated as empty.
+        let missing = temp_dir.path().join("missing");
+        assert!(config
+            .find_matched_rules(&missing, &FileHead::from_file(&missing))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_find_matched_rule_relative_to_config_root() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config_path = temp_dir.path().join("foro.json");
+        fs::write(
+            &config_path,
+            r#"{"rules": [{"on": {"glob": "src/*.ts"}, "cmd": {"io": "cat"}}]}"#,
+        )
+        .unwrap();
+
+        let config = load_file(&config_path).unwrap();
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+
+        assert_eq!(config.root.as_deref(), Some(root.as_path()));
+        assert!(!config
+            .find_matched_rules(&root.join("src/a.ts"), &FileHead::default())
+            .is_empty());
+        assert!(config
+            .find_matched_rules(&root.join("lib/src/a.ts"), &FileHead::default())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_find_matched_rule_outside_config_root() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let repo_dir = tempfile::tempdir().unwrap();
+        let config_path = config_dir.path().join("config.json");
+        fs::write(
+            &config_path,
+            r#"{"rules": [{"on": {"glob": "src/*.ts"}, "cmd": {"io": "cat"}}]}"#,
+        )
+        .unwrap();
+        fs::create_dir(repo_dir.path().join(".git")).unwrap();
+
+        // Like the global config, the config isn't in the repository
+        let config = load_file(&config_path).unwrap();
+        let repo = fs::canonicalize(repo_dir.path()).unwrap();
+
+        assert!(!config
+            .find_matched_rules(&repo.join("src/a.ts"), &FileHead::default())
+            .is_empty());
+        assert!(config
+            .find_matched_rules(&repo.join("lib/src/a.ts"), &FileHead::default())
+            .is_empty());
     }
 
     #[test]
@@ -433,4 +1401,244 @@ mod tests {
         let result = load_file(&file_path);
         assert!(result.is_err(), "Should fail with invalid JSON content");
     }
+
+    const BASE_CONFIG: &str = r#"{
+        "rules": [
+            { "on": ".rs", "cmd": { "io": "base-rs" } },
+            { "on": ".ts", "cmd": { "io": "base-ts" } }
+        ],
+        "cache_dir": "/base/cache",
+        "socket_dir": "/base/socket"
+    }"#;
+
+    fn rule_ios(config: &Config) -> Vec<String> {
+        config
+            .rules
+            .iter()
+            .map(|rule| match &rule.cmd {
+                CommandWithControlFlow::Command(Command::CommandIO { io }) => io.clone(),
+                _ => panic!("Expected CommandIO"),
+            })
+            .collect()
+    }
+
+    fn load_extending(merge_rules: &str) -> Config {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("shared")).unwrap();
+        fs::write(temp_dir.path().join("shared/base.json"), BASE_CONFIG).unwrap();
+
+        let config_path = temp_dir.path().join("foro.json");
+        fs::write(
+            &config_path,
+            format!(
+                r#"{{
+                    "extends": "shared/base.json",
+                    "merge_rules": "{merge_rules}",
+                    "rules": [
+                        {{ "on": ".ts", "cmd": {{ "io": "own-ts" }} }},
+                        {{ "on": ".py", "cmd": {{ "io": "own-py" }} }}
+                    ],
+                    "cache_dir": "/own/cache"
+                }}"#
+            ),
+        )
+        .unwrap();
+
+        load_file(&config_path).unwrap()
+    }
+
+    #[test]
+    fn test_extends_prepend() {
+        let config = load_extending("prepend");
+
+        assert_eq!(
+            rule_ios(&config),
+            ["own-ts", "own-py", "base-rs", "base-ts"]
+        );
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/own/cache")));
+        assert_eq!(config.socket_dir, Some(PathBuf::from("/base/socket")));
+        assert!(config.extends.is_none());
+    }
+
+    #[test]
+    fn test_extends_resolves_base_dirs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("shared")).unwrap();
+        fs::write(
+            temp_dir.path().join("shared/base.json"),
+            r#"{"rules": [], "cache_dir": "cache", "socket_dir": "/base/socket"}"#,
+        )
+        .unwrap();
+
+        let config_path = temp_dir.path().join("foro.json");
+        fs::write(
+            &config_path,
+            r#"{"extends": "shared/base.json", "rules": []}"#,
+        )
+        .unwrap();
+
+        let config = load_file(&config_path).unwrap();
+        assert_eq!(
+            config.cache_dir,
+            Some(temp_dir.path().join("shared").join("cache"))
+        );
+        assert_eq!(config.socket_dir, Some(PathBuf::from("/base/socket")));
+    }
+
+    #[test]
+    fn test_extends_append() {
+        let config = load_extending("append");
+
+        assert_eq!(
+            rule_ios(&config),
+            ["base-rs", "base-ts", "own-ts", "own-py"]
+        );
+    }
+
+    #[test]
+    fn test_extends_override() {
+        let config = load_extending("override");
+
+        assert_eq!(rule_ios(&config), ["own-py", "base-rs", "own-ts"]);
+    }
+
+    #[test]
+    fn test_extends_chain_and_cycle() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let a = temp_dir.path().join("a.json");
+        let b = temp_dir.path().join("b.json");
+        let c = temp_dir.path().join("c.json");
+
+        fs::write(&a, r#"{"extends": "b.json", "rules": []}"#).unwrap();
+        fs::write(&b, r#"{"extends": "c.json", "rules": []}"#).unwrap();
+        fs::write(&c, BASE_CONFIG).unwrap();
+
+        let (config, sources) = load_file_with_sources(&a).unwrap();
+        assert_eq!(rule_ios(&config), ["base-rs", "base-ts"]);
+        assert_eq!(
+            sources,
+            [&a, &b, &c].map(|path| fs::canonicalize(path).unwrap())
+        );
+
+        fs::write(&c, r#"{"extends": "a.json", "rules": []}"#).unwrap();
+
+        let err = load_file(&a).unwrap_err();
+        assert!(
+            err.to_string().contains("cycle detected"),
+            "Expected a cycle error, got: {err}"
+        );
+    }
+
+    #[test]
+    fn test_load_str_jsonc() {
+        let config = load_str(
+            r#"{
+                // line comment
+                "rules": [
+                    /* block comment */
+                    { "on": ".rs", "cmd": { "io": "rustfmt" }, },
+                ],
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(rule_ios(&config), ["rustfmt"]);
+    }
+
+    #[test]
+    fn test_load_str_errors_have_line_and_column() {
+        // A syntax error, reported by the JSON5 parser
+        let err = load_str("{\n  \"rules\": [\n    { \"on\": }\n  ]\n}").unwrap_err();
+        assert!(err.to_string().contains("at line 3 column"), "{err}");
+
+        // A data error in plain JSON, reported by serde_json
+        let err = load_str("{\n  \"rules\": [\n    { \"on\": \".rs\" }\n  ]\n}").unwrap_err();
+        assert!(err.to_string().contains("missing field `cmd`"), "{err}");
+        assert!(err.to_string().contains("at line 3 column"), "{err}");
+    }
+
+    #[test]
+    fn test_load_file_toml() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("base.jsonc"), BASE_CONFIG).unwrap();
+
+        let config_path = temp_dir.path().join("foro.toml");
+        fs::write(
+            &config_path,
+            r#"
+extends = "base.jsonc"
+cache_dir = "/toml/cache"
+
+# comments are fine in TOML
+[[rules]]
+on = [".py", { shebang = "python3" }]
+cmd = { io = "ruff format -" }
+"#,
+        )
+        .unwrap();
+
+        let config = load_file(&config_path).unwrap();
+
+        assert_eq!(rule_ios(&config), ["ruff format -", "base-rs", "base-ts"]);
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/toml/cache")));
+        assert_eq!(config.socket_dir, Some(PathBuf::from("/base/socket")));
+
+        fs::write(&config_path, "[[rules]]\non = \".py\"\n").unwrap();
+
+        let err = load_file(&config_path).unwrap_err();
+        assert!(err.to_string().contains("line 1"), "{err}");
+        assert!(err.to_string().contains("missing field `cmd`"), "{err}");
+    }
+
+    #[test]
+    fn test_extends_missing_base() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config_path = temp_dir.path().join("foro.json");
+        fs::write(&config_path, r#"{"extends": "missing.json", "rules": []}"#).unwrap();
+
+        assert!(load_file(&config_path).is_err());
+    }
+
+    #[test]
+    fn test_read_url_config_cache() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        // Nothing listens on the discard port, so fetching fails right away
+        let url = Url::parse("http://127.0.0.1:9/base.json").unwrap();
+
+        assert!(read_url_config(&url, Some(cache_dir.path())).is_err());
+
+        let cached = cache_dir
+            .path()
+            .join("configs")
+            .join(format!("{:032x}", xxh3_128(url.as_str().as_bytes())));
+        fs::create_dir_all(cached.parent().unwrap()).unwrap();
+        fs::write(&cached, r#"{"rules": []}"#).unwrap();
+
+        // A fresh copy is used without fetching
+        assert_eq!(
+            read_url_config(&url, Some(cache_dir.path())).unwrap(),
+            br#"{"rules": []}"#
+        );
+
+        // A stale copy is used when the config can't be fetched
+        fs::File::options()
+            .write(true)
+            .open(&cached)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - URL_CONFIG_MAX_AGE * 2)
+            .unwrap();
+        assert_eq!(
+            read_url_config(&url, Some(cache_dir.path())).unwrap(),
+            br#"{"rules": []}"#
+        );
+    }
+
+    #[test]
+    fn test_config_schema_is_up_to_date() {
+        assert_eq!(
+            config_schema().trim_end(),
+            include_str!("../../foro.schema.json").trim_end(),
+            "foro.schema.json is outdated, regenerate it with `cargo run -- config schema > foro.schema.json`"
+        );
+    }
 }
@@ -0,0 +1,476 @@
+use crate::config::{load_file, ConfigFormat, MergeRules, OnRule, RuleCache, RuleMode};
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use url::Url;
+
+const CONFIG_KEYS: [&str; 7] = [
+    "$schema",
+    "extends",
+    "merge_rules",
+    "mode",
+    "rules",
+    "cache_dir",
+    "socket_dir",
+];
+const RULE_KEYS: [&str; 4] = ["on", "cmd", "mode", "cache"];
+const IF_KEYS: [&str; 4] = ["run", "cond", "on_true", "on_false"];
+const SET_KEYS: [&str; 1] = ["set"];
+const IO_KEYS: [&str; 1] = ["io"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a config, located by its path (e.g. `rules[1].cmd.on_true`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Diagnostic {
+    pub severity: Severity,
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}: {}", self.severity, self.message)
+        } else {
+            write!(f, "{}: {}: {}", self.severity, self.path, self.message)
+        }
+    }
+}
+
+fn child(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn index(path: &str, i: usize) -> String {
+    format!("{path}[{i}]")
+}
+
+/// The closest known key to `key`, if it's likely a typo.
+fn closest_key<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|candidate| (strsim::levenshtein(key, candidate), *candidate))
+        .filter(|(distance, candidate)| *distance <= 2.max(candidate.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// A data-json like the one given to commands, used to check that `io` templates render.
+fn sample_data_json() -> Value {
+    json!({
+        "wasm-current-dir": "/",
+        "os-current-dir": "/",
+        "wasm-target": "/main.rs",
+        "os-target": "/main.rs",
+        "raw-target": "main.rs",
+        "target-content": "",
+    })
+}
+
+#[derive(Default)]
+struct Validator {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator {
+    fn push(&mut self, severity: Severity, path: &str, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            path: path.to_string(),
+            message: message.into(),
+        });
+    }
+
+    fn error(&mut self, path: &str, message: impl Into<String>) {
+        self.push(Severity::Error, path, message);
+    }
+
+    fn warning(&mut self, path: &str, message: impl Into<String>) {
+        self.push(Severity::Warning, path, message);
+    }
+
+    /// Unknown keys are ignored by serde, so they are only reported as warnings.
+    fn unknown_keys(&mut self, path: &str, map: &Map<String, Value>, known: &[&str]) {
+        for key in map.keys().filter(|key| !known.contains(&key.as_str())) {
+            let message = match closest_key(key, known) {
+                Some(closest) => {
+                    format!("unknown field `{key}` is ignored (did you mean `{closest}`?)")
+                }
+                None => format!("unknown field `{key}` is ignored"),
+            };
+            self.warning(path, message);
+        }
+    }
+
+    fn deserialize<T: DeserializeOwned>(&mut self, path: &str, value: &Value) {
+        if let Err(err) = serde_json::from_value::<T>(value.clone()) {
+            self.error(path, err.to_string());
+        }
+    }
+
+    fn optional_string(&mut self, path: &str, value: Option<&Value>) {
+        match value {
+            None | Some(Value::Null) | Some(Value::String(_)) => {}
+            Some(other) => self.error(path, format!("expected a string, got `{other}`")),
+        }
+    }
+
+    fn config(&mut self, value: &Value) {
+        let Some(map) = value.as_object() else {
+            self.error("", "the config must be an object");
+            return;
+        };
+
+        self.unknown_keys("", map, &CONFIG_KEYS);
+        self.optional_string("extends", map.get("extends"));
+        self.optional_string("cache_dir", map.get("cache_dir"));
+        self.optional_string("socket_dir", map.get("socket_dir"));
+
+        if let Some(merge_rules) = map.get("merge_rules") {
+            self.deserialize::<MergeRules>("merge_rules", merge_rules);
+        }
+        if let Some(mode) = map.get("mode") {
+            self.deserialize::<RuleMode>("mode", mode);
+        }
+
+        match map.get("rules") {
+            Some(Value::Array(rules)) => {
+                for (i, rule) in rules.iter().enumerate() {
+                    self.rule(&index("rules", i), rule);
+                }
+            }
+            Some(other) => self.error("rules", format!("expected a list of rules, got `{other}`")),
+            None => self.error("", "missing field `rules`"),
+        }
+    }
+
+    fn rule(&mut self, path: &str, value: &Value) {
+        let Some(map) = value.as_object() else {
+            self.error(path, "a rule must be an object with `on` and `cmd`");
+            return;
+        };
+
+        self.unknown_keys(path, map, &RULE_KEYS);
+
+        match map.get("on") {
+            Some(on) => self.deserialize::<OnRule>(&child(path, "on"), on),
+            None => self.error(path, "missing field `on`"),
+        }
+        match map.get("cmd") {
+            Some(cmd) => self.flow(&child(path, "cmd"), cmd),
+            None => self.error(path, "missing field `cmd`"),
+        }
+        if let Some(mode) = map.get("mode") {
+            self.deserialize::<RuleMode>(&child(path, "mode"), mode);
+        }
+        if let Some(cache) = map.get("cache") {
+            self.deserialize::<RuleCache>(&child(path, "cache"), cache);
+        }
+    }
+
+    /// Check a `CommandWithControlFlow`, trying the variants in the same order as serde.
+    fn flow(&mut self, path: &str, value: &Value) {
+        match value {
+            Value::String(url) => {
+                if let Err(err) = Url::parse(url) {
+                    self.error(path, format!("invalid plugin URL `{url}`: {err}"));
+                }
+            }
+            Value::Array(commands) => {
+                for (i, command) in commands.iter().enumerate() {
+                    self.flow(&index(path, i), command);
+                }
+            }
+            Value::Object(map) => {
+                let has = |keys: &[&str]| keys.iter().filter(|k| map.contains_key(**k)).count();
+
+                if has(&IF_KEYS) == IF_KEYS.len() {
+                    self.unknown_keys(path, map, &IF_KEYS);
+                    self.if_flow(path, map);
+                } else if has(&SET_KEYS) > 0 {
+                    self.unknown_keys(path, map, &SET_KEYS);
+                    self.set(&child(path, "set"), &map["set"]);
+                } else if has(&IO_KEYS) > 0 {
+                    self.unknown_keys(path, map, &IO_KEYS);
+                    self.io(&child(path, "io"), &map["io"]);
+                } else if has(&IF_KEYS) > 0 {
+                    let missing = IF_KEYS
+                        .iter()
+                        .filter(|key| !map.contains_key(**key))
+                        .map(|key| format!("`{key}`"))
+                        .collect::<Vec<_>>();
+                    self.error(
+                        path,
+                        format!(
+                            "the closest command is `if`, but it is missing {}",
+                            missing.join(", ")
+                        ),
+                    );
+                } else {
+                    let known = [IF_KEYS.as_slice(), &SET_KEYS, &IO_KEYS].concat();
+                    let hint = map
+                        .keys()
+                        .find_map(|key| closest_key(key, &known).map(|c| (key, c)))
+                        .map(|(key, closest)| format!(" (`{key}` looks like `{closest}`)"))
+                        .unwrap_or_default();
+                    self.error(
+                        path,
+                        format!(
+                            "unknown command: expected `io`, `set` or `run`/`cond`/`on_true`/`on_false`{hint}"
+                        ),
+                    );
+                }
+            }
+            other => self.error(
+                path,
+                format!(
+                    "expected a plugin URL, a list of commands or a command object, got `{other}`"
+                ),
+            ),
+        }
+    }
+
+    fn if_flow(&mut self, path: &str, map: &Map<String, Value>) {
+        match &map["cond"] {
+            Value::String(cond) => {
+                let env = minijinja::Environment::new();
+                if let Err(err) = env.compile_expression(cond) {
+                    self.error(&child(path, "cond"), format!("invalid expression: {err}"));
+                }
+            }
+            other => self.error(
+                &child(path, "cond"),
+                format!("expected an expression string, got `{other}`"),
+            ),
+        }
+
+        for key in ["run", "on_true", "on_false"] {
+            self.flow(&child(path, key), &map[key]);
+        }
+    }
+
+    fn set(&mut self, path: &str, value: &Value) {
+        let Some(map) = value.as_object() else {
+            self.error(
+                path,
+                format!("expected an object of expressions, got `{value}`"),
+            );
+            return;
+        };
+
+        let env = minijinja::Environment::new();
+
+        for (key, expr) in map {
+            let expr_path = child(path, key);
+
+            match expr {
+                Value::String(expr) => {
+                    if let Err(err) = env.compile_expression(expr) {
+                        self.error(&expr_path, format!("invalid expression: {err}"));
+                    }
+                }
+                other => self.error(
+                    &expr_path,
+                    format!("expected an expression string, got `{other}`"),
+                ),
+            }
+        }
+    }
+
+    fn io(&mut self, path: &str, value: &Value) {
+        let Value::String(io) = value else {
+            self.error(path, format!("expected a command string, got `{value}`"));
+            return;
+        };
+
+        let env = minijinja::Environment::new();
+        let rendered = match env.render_str(io, sample_data_json()) {
+            Ok(rendered) => rendered,
+            Err(err) => {
+                self.error(path, format!("failed to render the template: {err}"));
+                return;
+            }
+        };
+
+        #[cfg(windows)]
+        {
+            let _ = rendered;
+            self.warning(path, "CommandIO is not supported on Windows");
+        }
+
+        #[cfg(not(windows))]
+        match shell_words::split(&rendered) {
+            Ok(words) if words.is_empty() => self.error(path, "empty command"),
+            Ok(_) => {}
+            Err(err) => self.error(path, format!("failed to split the command: {err}")),
+        }
+    }
+}
+
+/// Check the structure of a raw config value, explaining untagged mismatches.
+pub(crate) fn validate_value(value: &Value) -> Vec<Diagnostic> {
+    let mut validator = Validator::default();
+    validator.config(value);
+    validator.diagnostics
+}
+
+/// Validate a config file. Syntax errors are returned as `Err`.
+///
+/// If the structure is valid, the config is also loaded to check its `extends` chain.
+pub(crate) fn validate_file(path: &Path) -> Result<Vec<Diagnostic>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read config file ({path:?})"))?;
+    let value: Value = ConfigFormat::from_path(&path.to_string_lossy())
+        .parse(&bytes)
+        .with_context(|| format!("Failed to parse config file ({path:?})"))?;
+
+    let mut diagnostics = validate_value(&value);
+
+    if diagnostics.iter().all(|d| d.severity != Severity::Error) {
+        if let Err(err) = load_file(path) {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                path: String::new(),
+                message: format!("{err:#}"),
+            });
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+
+    fn messages(json: &str) -> Vec<String> {
+        let value: Value = serde_json::from_str(json).unwrap();
+        validate_value(&value)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_valid_config() {
+        let config = include_str!("default_config.json");
+        assert_eq!(messages(config), Vec::<String>::new());
+
+        assert_eq!(
+            messages(
+                r#"{"rules": [{
+                    "on": ".py",
+                    "cmd": {
+                        "run": [{"set": {"x": "1 + 1"}}, {"io": "ruff format --stdin-filename {{ raw_target }} -"}],
+                        "cond": "x == 2",
+                        "on_true": "https://example.com/a.dllpack",
+                        "on_false": {"io": "cat"}
+                    }
+                }]}"#
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_validate_closest_variant() {
+        assert_eq!(
+            messages(
+                r#"{"rules": [{"on": ".rs", "cmd": {"run": "https://example.com/a", "cond": "true", "on_true": {"io": "cat"}}}]}"#
+            ),
+            ["error: rules[0].cmd: the closest command is `if`, but it is missing `on_false`"]
+        );
+        assert_eq!(
+            messages(r#"{"rules": [{"on": ".rs", "cmd": {"iop": "cat"}}]}"#),
+            ["error: rules[0].cmd: unknown command: expected `io`, `set` or `run`/`cond`/`on_true`/`on_false` (`iop` looks like `io`)"]
+        );
+        assert_eq!(
+            messages(r#"{"rules": [{"on": ".rs", "cmd": 1}]}"#),
+            ["error: rules[0].cmd: expected a plugin URL, a list of commands or a command object, got `1`"]
+        );
+    }
+
+    #[test]
+    fn test_validate_expressions_templates_and_urls() {
+        assert_eq!(
+            messages(
+                r#"{"rules": [{"on": ".rs", "cmd": [
+                    "not a url",
+                    {"set": {"x": "1 +"}},
+                    {"io": "fmt {{ unclosed"},
+                    {"io": "fmt 'unclosed"},
+                    {"run": {"io": "cat"}, "cond": "(", "on_true": [], "on_false": []}
+                ]}]}"#
+            )
+            .iter()
+            .map(|m| m.split(": ").take(2).collect::<Vec<_>>().join(": "))
+            .collect::<Vec<_>>(),
+            [
+                "error: rules[0].cmd[0]",
+                "error: rules[0].cmd[1].set.x",
+                "error: rules[0].cmd[2].io",
+                "error: rules[0].cmd[3].io",
+                "error: rules[0].cmd[4].cond",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_config_and_rule_fields() {
+        assert_eq!(
+            messages(r#"{"rule": [], "mode": "any"}"#),
+            [
+                "warning: unknown field `rule` is ignored (did you mean `rules`?)",
+                "error: mode: unknown variant `any`, expected `first` or `all`",
+                "error: missing field `rules`",
+            ]
+        );
+        assert_eq!(
+            messages(r#"{"rules": [{"on": {"glob": "*", "not": ".rs"}, "command": "x"}]}"#),
+            [
+                "warning: rules[0]: unknown field `command` is ignored",
+                "error: rules[0].on: invalid `on` rule: an object must have exactly one of the keys [\"glob\", \"filename\", \"suffix\", \"shebang\", \"content\", \"all\", \"not\"]",
+                "error: rules[0]: missing field `cmd`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_file_checks_extends() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let config_path = temp_dir.path().join("foro.json");
+        fs::write(&config_path, r#"{"extends": "missing.json", "rules": []}"#)?;
+
+        let diagnostics = validate_file(&config_path)?;
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message
+            .contains("Failed to load extended config"));
+
+        fs::write(&config_path, "{ \"rules\": [ }")?;
+        assert!(validate_file(&config_path).is_err());
+
+        Ok(())
+    }
+}
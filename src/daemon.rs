@@ -1,5 +1,8 @@
 pub mod client;
+pub mod config_cache;
+pub mod framing;
 pub mod interface;
 pub mod server;
 pub mod startup_lock;
 mod uds;
+pub mod watch;
@@ -1,16 +1,25 @@
 use crate::build_info::get_build_id;
+use crate::daemon::framing::{
+    read_frame, write_frame, DaemonRequestFrame, DaemonResponseFrame, FRAMED_MAGIC,
+};
 use crate::daemon::interface::{
-    BulkFormatSummary, DaemonBulkFormatResponse, DaemonCommandPayload, DaemonCommands,
-    DaemonExecutionOptions, DaemonFormatResponse, DaemonResponse, DaemonSocketPath,
+    BulkFormatProgress, BulkFormatSummary, DaemonBulkFormatResponse, DaemonCommandPayload,
+    DaemonCommands, DaemonExecutionOptions, DaemonFormatResponse, DaemonResponse, DaemonSocketPath,
+    DaemonWatchResponse, FileDiff, FileResult, FileStatus,
 };
 use crate::daemon::server::start_daemon;
 use crate::daemon::startup_lock::StartupLock;
 use crate::daemon::uds::UnixStream;
+use crate::diff::render_unified_diff;
 use crate::process_utils::{get_start_time, is_alive};
 use anyhow::{anyhow, Result};
 use log::{debug, info, warn};
+use std::collections::HashMap;
 use std::env::current_dir;
-use std::io::{ErrorKind, Write};
+use std::io::{BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 fn parse_info(info_str: &str) -> Option<(u32, u64, String)> {
@@ -121,12 +130,10 @@ pub fn ping(socket: &DaemonSocketPath) -> Result<bool> {
     }
 }
 
-fn run_command_inner(
+fn build_payload(
     command: DaemonCommands,
     mut execution_options: DaemonExecutionOptions,
-    mut stream: UnixStream,
-    timeout: Option<Duration>,
-) -> Result<DaemonResponse> {
+) -> Result<DaemonCommandPayload> {
     let cwd = current_dir()?;
 
     // Convert relative config_file path to absolute path
@@ -134,11 +141,20 @@ fn run_command_inner(
         execution_options.config_file = Some(cwd.join(config_file).canonicalize()?);
     }
 
-    let buf = serde_json::to_vec(&DaemonCommandPayload {
+    Ok(DaemonCommandPayload {
         command,
         current_dir: cwd,
         execution_options,
-    })?;
+    })
+}
+
+fn run_command_inner(
+    command: DaemonCommands,
+    execution_options: DaemonExecutionOptions,
+    mut stream: UnixStream,
+    timeout: Option<Duration>,
+) -> Result<DaemonResponse> {
+    let buf = serde_json::to_vec(&build_payload(command, execution_options)?)?;
     stream.write_all(&buf)?;
 
     debug!("Sent command");
@@ -224,28 +240,81 @@ pub fn run_command(
         }
     }
 
+    let (check, diff) = match &command {
+        DaemonCommands::Format(args) => (args.check, args.diff),
+        DaemonCommands::BulkFormat(args) => (args.check, args.diff),
+        _ => (false, false),
+    };
+
+    // When formatting stdin, an ignored file must still be echoed back to stdout
+    let passthrough_content = match &command {
+        DaemonCommands::Format(args) if args.return_edits => Some("[]\n".to_string()),
+        DaemonCommands::Format(args) if args.return_content => Some(args.content.clone()),
+        _ => None,
+    };
+
     let stream = UnixStream::connect(&socket.socket_path)?;
 
     match run_command_inner(command, execution_options, stream, None)? {
         DaemonResponse::Format(DaemonFormatResponse::Success()) => {
-            eprintln!("Formatted successfully.");
+            if check || diff {
+                eprintln!("Already formatted.");
+            } else {
+                eprintln!("Formatted successfully.");
+            }
+        }
+        DaemonResponse::Format(DaemonFormatResponse::Unformatted(path)) => {
+            println!("Would reformat: {}", relative_to_cwd(&path).display());
+            return Err(anyhow!("File is not formatted"));
+        }
+        DaemonResponse::Format(DaemonFormatResponse::Diff(file_diff)) => {
+            print!("{}", render_file_diff(&file_diff));
+
+            if check {
+                return Err(anyhow!("File is not formatted"));
+            }
         }
         DaemonResponse::Format(DaemonFormatResponse::Ignored(reason)) => {
             eprintln!("File ignored: {}", reason);
+
+            if let Some(content) = passthrough_content {
+                print!("{}", content);
+            }
+        }
+        DaemonResponse::Format(DaemonFormatResponse::Content(content)) => {
+            print!("{}", content);
+        }
+        DaemonResponse::Format(DaemonFormatResponse::Edits(edits)) => {
+            println!("{}", serde_json::to_string(&edits)?);
         }
         DaemonResponse::Format(DaemonFormatResponse::Error(err)) => {
             return Err(anyhow!(err));
         }
         DaemonResponse::BulkFormat(DaemonBulkFormatResponse::Success(summary)) => {
-            let message = format_bulk_success_message(summary);
+            print_file_errors(&summary);
+            print_bulk_format_summary(&summary, check, diff)?;
+        }
+        DaemonResponse::BulkFormat(DaemonBulkFormatResponse::Error(err)) => {
+            return Err(anyhow!(err));
+        }
+        DaemonResponse::BulkFormatProgress(event) => {
+            return Err(anyhow!("Unexpected progress event: {event:?}"));
+        }
+        DaemonResponse::Cancel(found) => {
+            if !found {
+                eprintln!("No such request is running.");
+            }
+        }
+        DaemonResponse::Watch(DaemonWatchResponse::Watching(paths)) => {
+            if paths.is_empty() {
+                eprintln!("Not watching any paths.");
+            }
 
-            if summary.error_count == 0 {
-                eprintln!("Formatted successfully: {}", message);
-            } else {
-                return Err(anyhow!("Formatted with errors: {}", message));
+            for path in &paths {
+                println!("Watching: {}", path.display());
             }
         }
-        DaemonResponse::BulkFormat(DaemonBulkFormatResponse::Error(err)) => {
+        DaemonResponse::Watch(DaemonWatchResponse::Error(err)) => {
             return Err(anyhow!(err));
         }
         DaemonResponse::Stop => {
@@ -263,12 +332,206 @@ pub fn run_command(
     Ok(())
 }
 
-fn format_bulk_success_message(summary: BulkFormatSummary) -> String {
-    let error_label = if summary.error_count == 1 {
+/// A connection to the daemon that carries many requests, with the framed protocol.
+///
+/// Requests can be pipelined with [DaemonConnection::send], and their responses
+/// collected with [DaemonConnection::wait] in any order.
+pub struct DaemonConnection {
+    reader: BufReader<UnixStream>,
+    sender: Arc<DaemonSender>,
+    /// Responses that arrived while waiting for another request
+    pending: HashMap<u64, DaemonResponse>,
+}
+
+/// The sending half of a [DaemonConnection]. It can be used from another thread,
+/// e.g. to cancel a request while waiting for it.
+pub struct DaemonSender {
+    writer: Mutex<UnixStream>,
+    next_id: AtomicU64,
+}
+
+impl DaemonSender {
+    /// Send a request without waiting for its response. Returns the id of the request.
+    pub fn send(
+        &self,
+        command: DaemonCommands,
+        execution_options: DaemonExecutionOptions,
+    ) -> Result<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        write_frame(
+            &mut *self.writer.lock().unwrap(),
+            &DaemonRequestFrame {
+                id,
+                payload: build_payload(command, execution_options)?,
+            },
+        )?;
+
+        Ok(id)
+    }
+}
+
+impl DaemonConnection {
+    pub fn connect(socket: &DaemonSocketPath) -> Result<Self> {
+        let mut writer = UnixStream::connect(&socket.socket_path)?;
+        writer.write_all(FRAMED_MAGIC)?;
+
+        Ok(Self {
+            reader: BufReader::new(writer.try_clone()?),
+            sender: Arc::new(DaemonSender {
+                writer: Mutex::new(writer),
+                next_id: AtomicU64::new(0),
+            }),
+            pending: HashMap::new(),
+        })
+    }
+
+    pub fn sender(&self) -> Arc<DaemonSender> {
+        self.sender.clone()
+    }
+
+    /// Send a request without waiting for its response. Returns the id of the request.
+    pub fn send(
+        &mut self,
+        command: DaemonCommands,
+        execution_options: DaemonExecutionOptions,
+    ) -> Result<u64> {
+        self.sender.send(command, execution_options)
+    }
+
+    /// Wait for the response to the request `id`.
+    pub fn wait(&mut self, id: u64) -> Result<DaemonResponse> {
+        self.wait_with_progress(id, |_| {})
+    }
+
+    /// Wait for the response to the request `id`, passing its progress events to `on_progress`.
+    /// Progress events of other requests are dropped.
+    pub fn wait_with_progress(
+        &mut self,
+        id: u64,
+        mut on_progress: impl FnMut(BulkFormatProgress),
+    ) -> Result<DaemonResponse> {
+        if let Some(response) = self.pending.remove(&id) {
+            return Ok(response);
+        }
+
+        loop {
+            let frame: DaemonResponseFrame = read_frame(&mut self.reader)?
+                .ok_or_else(|| anyhow!("The daemon closed the connection"))?;
+
+            match frame.response {
+                DaemonResponse::BulkFormatProgress(event) => {
+                    if frame.id == id {
+                        on_progress(event);
+                    }
+                }
+                response if frame.id == id => return Ok(response),
+                response => {
+                    self.pending.insert(frame.id, response);
+                }
+            }
+        }
+    }
+
+    pub fn request(
+        &mut self,
+        command: DaemonCommands,
+        execution_options: DaemonExecutionOptions,
+    ) -> Result<DaemonResponse> {
+        let id = self.send(command, execution_options)?;
+        self.wait(id)
+    }
+}
+
+/// Show `path` relative to the current directory if possible, for shorter output.
+pub(crate) fn relative_to_cwd(path: &Path) -> PathBuf {
+    current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+pub(crate) fn render_file_diff(file_diff: &FileDiff) -> String {
+    let path = relative_to_cwd(&file_diff.path);
+    render_unified_diff(&path.display().to_string(), &file_diff.hunks)
+}
+
+/// Print the outcome of a bulk format, failing if there were errors (or changes with `check`).
+pub(crate) fn print_bulk_format_summary(
+    summary: &BulkFormatSummary,
+    check: bool,
+    diff: bool,
+) -> Result<()> {
+    if check || diff {
+        if diff {
+            for file_diff in &summary.diffs {
+                print!("{}", render_file_diff(file_diff));
+            }
+        } else {
+            for file in &summary.files {
+                if file.status == FileStatus::Changed {
+                    println!("Would reformat: {}", relative_to_cwd(&file.path).display());
+                }
+            }
+        }
+
+        let message = format_bulk_check_message(summary);
+
+        if summary.cancelled {
+            return Err(anyhow!("Cancelled: {}", message));
+        } else if check && (summary.error_count > 0 || summary.changed_count > 0) {
+            return Err(anyhow!("Check failed: {}", message));
+        } else if summary.error_count > 0 {
+            return Err(anyhow!("Diff failed: {}", message));
+        } else if summary.changed_count == 0 {
+            eprintln!("All files are formatted: {}", message);
+        } else {
+            eprintln!("Dry run: {}", message);
+        }
+    } else {
+        let message = format_bulk_success_message(summary);
+
+        if summary.cancelled {
+            return Err(anyhow!("Cancelled: {}", message));
+        } else if summary.error_count > 0 {
+            return Err(anyhow!("Formatted with errors: {}", message));
+        }
+
+        eprintln!("Formatted successfully: {}", message);
+    }
+
+    Ok(())
+}
+
+/// Print the files that failed to format, with the reason.
+pub(crate) fn print_file_errors(summary: &BulkFormatSummary) {
+    for file in &summary.files {
+        print_file_error(file);
+    }
+}
+
+/// Print the reason a file failed to format, if it did.
+pub(crate) fn print_file_error(file: &FileResult) {
+    if file.status == FileStatus::Error {
+        eprintln!(
+            "Error: {}: {}",
+            relative_to_cwd(&file.path).display(),
+            file.message.as_deref().unwrap_or("Failed to format")
+        );
+    }
+}
+
+fn error_label(error_count: usize) -> &'static str {
+    if error_count == 1 {
         "error"
     } else {
         "errors"
-    };
+    }
+}
+
+pub(crate) fn format_bulk_success_message(summary: &BulkFormatSummary) -> String {
+    let error_label = error_label(summary.error_count);
 
     format!(
         "{} files processed. {} changed, {} unchanged, {} ignored, {} {}.",
@@ -281,9 +544,23 @@ fn format_bulk_success_message(summary: BulkFormatSummary) -> String {
     )
 }
 
+pub(crate) fn format_bulk_check_message(summary: &BulkFormatSummary) -> String {
+    format!(
+        "{} files checked. {} would be reformatted, {} already formatted, {} ignored, {} {}.",
+        summary.total_count,
+        summary.changed_count,
+        summary.unchanged_count,
+        summary.ignored_count,
+        summary.error_count,
+        error_label(summary.error_count)
+    )
+}
+
 #[cfg(test)]
 mod tests {
-    use super::format_bulk_success_message;
+    use super::{
+        format_bulk_check_message, format_bulk_success_message, print_bulk_format_summary,
+    };
     use crate::daemon::interface::BulkFormatSummary;
 
     #[test]
@@ -294,10 +571,13 @@ mod tests {
             unchanged_count: 3,
             ignored_count: 0,
             error_count: 0,
+            diffs: Vec::new(),
+            files: Vec::new(),
+            cancelled: false,
         };
 
         assert_eq!(
-            format_bulk_success_message(summary),
+            format_bulk_success_message(&summary),
             "3 files processed. 0 changed, 3 unchanged, 0 ignored, 0 errors."
         );
     }
@@ -310,10 +590,13 @@ mod tests {
             unchanged_count: 1,
             ignored_count: 0,
             error_count: 0,
+            diffs: Vec::new(),
+            files: Vec::new(),
+            cancelled: false,
         };
 
         assert_eq!(
-            format_bulk_success_message(summary),
+            format_bulk_success_message(&summary),
             "3 files processed. 2 changed, 1 unchanged, 0 ignored, 0 errors."
         );
     }
@@ -326,11 +609,53 @@ mod tests {
             unchanged_count: 4,
             ignored_count: 0,
             error_count: 0,
+            diffs: Vec::new(),
+            files: Vec::new(),
+            cancelled: false,
         };
 
         assert_eq!(
-            format_bulk_success_message(summary),
+            format_bulk_success_message(&summary),
             "5 files processed. 1 changed, 4 unchanged, 0 ignored, 0 errors."
         );
     }
+
+    #[test]
+    fn format_bulk_check_message_reports_unformatted_files() {
+        let summary = BulkFormatSummary {
+            total_count: 4,
+            changed_count: 2,
+            unchanged_count: 1,
+            ignored_count: 0,
+            error_count: 1,
+            diffs: Vec::new(),
+            files: Vec::new(),
+            cancelled: false,
+        };
+
+        assert_eq!(
+            format_bulk_check_message(&summary),
+            "4 files checked. 2 would be reformatted, 1 already formatted, 0 ignored, 1 error."
+        );
+    }
+
+    #[test]
+    fn print_bulk_format_summary_reports_check_only_with_check() {
+        let summary = BulkFormatSummary {
+            total_count: 2,
+            changed_count: 0,
+            unchanged_count: 1,
+            ignored_count: 0,
+            error_count: 1,
+            diffs: Vec::new(),
+            files: Vec::new(),
+            cancelled: false,
+        };
+
+        let err = print_bulk_format_summary(&summary, false, true).unwrap_err();
+        assert!(err.to_string().starts_with("Diff failed: "), "{err}");
+
+        let err = print_bulk_format_summary(&summary, true, true).unwrap_err();
+        assert!(err.to_string().starts_with("Check failed: "), "{err}");
+    }
 }
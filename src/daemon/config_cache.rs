@@ -0,0 +1,323 @@
+use crate::config::{cache_dir_for, get_or_create_default_config, load_file_with_sources, Config};
+use crate::install_check::{check_ready, ready_marker};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// The config cache shared by all requests to the daemon.
+pub static CONFIG_CACHE: LazyLock<ConfigCache> = LazyLock::new(ConfigCache::new);
+
+struct CachedConfig {
+    config: Arc<Config>,
+    /// The local files the config was built from. A change to any of them invalidates it.
+    sources: Vec<PathBuf>,
+    /// Ready markers by the cache directories that the plugins of the config are known
+    /// to be installed in.
+    ready_in: HashMap<PathBuf, PathBuf>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<PathBuf, CachedConfig>,
+    watched_files: HashSet<PathBuf>,
+    watched_dirs: HashSet<PathBuf>,
+    /// Bumped on every change to a watched file, so that a config that was loaded
+    /// while one of its files changed isn't cached.
+    generation: u64,
+}
+
+impl State {
+    fn handle_event(&mut self, event: notify::Result<Event>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                warn!("Config watcher error, dropping all cached configs: {err}");
+                self.generation += 1;
+                self.entries.clear();
+                return;
+            }
+        };
+
+        // Reading a config file causes access events, which must not invalidate it
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+
+        if !event
+            .paths
+            .iter()
+            .any(|path| self.watched_files.contains(path))
+        {
+            return;
+        }
+
+        debug!("Config changed: {:?}", event.paths);
+
+        self.generation += 1;
+        self.entries.retain(|_, entry| {
+            !entry
+                .sources
+                .iter()
+                .any(|source| event.paths.contains(source))
+        });
+    }
+}
+
+/// Parsed configs keyed by their file, which are invalidated when the file
+/// (or a local file it extends) changes.
+///
+/// Configs extended from URLs are cached until a local file of the config changes.
+/// If the files can't be watched, configs are loaded on every request instead.
+pub struct ConfigCache {
+    state: Arc<Mutex<State>>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl ConfigCache {
+    pub fn new() -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let handler_state = state.clone();
+        let watcher = notify::recommended_watcher(move |event| {
+            handler_state.lock().unwrap().handle_event(event);
+        })
+        .inspect_err(|err| warn!("Failed to create config watcher: {err}"))
+        .ok();
+
+        Self {
+            state,
+            watcher: Mutex::new(watcher),
+        }
+    }
+
+    /// Like `load_config_and_cache` followed by `check_ready`, but from the cache if possible.
+    pub fn load(
+        &self,
+        config_file: Option<&Path>,
+        given_cache_dir: Option<&Path>,
+    ) -> Result<(Arc<Config>, PathBuf)> {
+        let config_file = match config_file {
+            Some(path) => path.to_path_buf(),
+            None => get_or_create_default_config().context("Failed to get config directory")?,
+        };
+
+        let config = self
+            .config(&config_file)
+            .with_context(|| format!("Failed to load config file ({config_file:?})"))?;
+        let cache_dir = cache_dir_for(&config, given_cache_dir)?;
+
+        self.check_ready(&config_file, &config, &cache_dir)
+            .with_context(|| {
+                format!("Plugins not installed for {config_file:?}: run `foro install` first")
+            })?;
+
+        Ok((config, cache_dir))
+    }
+
+    fn config(&self, config_file: &Path) -> Result<Arc<Config>> {
+        {
+            let state = self.state.lock().unwrap();
+            if let Some(entry) = state.entries.get(config_file) {
+                return Ok(entry.config.clone());
+            }
+        }
+
+        let watching = self.watch(config_file);
+        let generation = self.state.lock().unwrap().generation;
+
+        let (config, sources) = load_file_with_sources(config_file)?;
+        let config = Arc::new(config);
+
+        if watching && sources.iter().all(|source| self.watch(source)) {
+            let mut state = self.state.lock().unwrap();
+
+            if state.generation == generation {
+                debug!("Caching config: {config_file:?}");
+
+                state.entries.insert(
+                    config_file.to_path_buf(),
+                    CachedConfig {
+                        config: config.clone(),
+                        sources,
+                        ready_in: HashMap::new(),
+                    },
+                );
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Check that the plugins are installed, remembering the ready marker once they are.
+    ///
+    /// The marker is still checked for existence, so that a cleaned cache is noticed.
+    fn check_ready(&self, config_file: &Path, config: &Config, cache_dir: &Path) -> Result<()> {
+        let known_marker = self
+            .state
+            .lock()
+            .unwrap()
+            .entries
+            .get(config_file)
+            .and_then(|entry| entry.ready_in.get(cache_dir).cloned());
+
+        if known_marker.is_some_and(|marker| marker.exists()) {
+            return Ok(());
+        }
+
+        check_ready(config, cache_dir)?;
+
+        if let Some(entry) = self.state.lock().unwrap().entries.get_mut(config_file) {
+            entry
+                .ready_in
+                .insert(cache_dir.to_path_buf(), ready_marker(config, cache_dir));
+        }
+
+        Ok(())
+    }
+
+    /// Start watching `file`. Its directory is watched instead of the file itself,
+    /// since editors often save by replacing the file.
+    fn watch(&self, file: &Path) -> bool {
+        let file = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        let Some(dir) = file.parent() else {
+            return false;
+        };
+
+        if !self.state.lock().unwrap().watched_dirs.contains(dir) {
+            let mut watcher = self.watcher.lock().unwrap();
+            let Some(watcher) = watcher.as_mut() else {
+                return false;
+            };
+
+            if let Err(err) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                warn!("Failed to watch config directory {dir:?}: {err}");
+                return false;
+            }
+
+            self.state
+                .lock()
+                .unwrap()
+                .watched_dirs
+                .insert(dir.to_path_buf());
+        }
+
+        self.state.lock().unwrap().watched_files.insert(file);
+
+        true
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use crate::install_check::mark_ready;
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    /// Wait until the cached config of `path` is invalidated by the watcher.
+    fn wait_for_invalidation(cache: &ConfigCache, path: &Path) {
+        let start = Instant::now();
+
+        while cache.state.lock().unwrap().entries.contains_key(path) {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "The config was not invalidated"
+            );
+            sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_config_cache_reuses_and_reloads() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let config_path = fs::canonicalize(temp_dir.path())?.join("foro.json");
+        fs::write(&config_path, r#"{"rules": []}"#)?;
+
+        let cache = ConfigCache::new();
+
+        let first = cache.config(&config_path)?;
+        let second = cache.config(&config_path)?;
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(first.rules.len(), 0);
+
+        fs::write(
+            &config_path,
+            r#"{"rules": [{"on": ".rs", "cmd": {"io": "rustfmt"}}]}"#,
+        )?;
+        wait_for_invalidation(&cache, &config_path);
+
+        assert_eq!(cache.config(&config_path)?.rules.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_cache_watches_extended_files() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = fs::canonicalize(temp_dir.path())?;
+        fs::create_dir(dir.join("shared"))?;
+
+        let base_path = dir.join("shared/base.json");
+        fs::write(&base_path, r#"{"rules": []}"#)?;
+
+        let config_path = dir.join("foro.json");
+        fs::write(
+            &config_path,
+            r#"{"extends": "shared/base.json", "rules": []}"#,
+        )?;
+
+        let cache = ConfigCache::new();
+        assert_eq!(cache.config(&config_path)?.rules.len(), 0);
+
+        fs::write(
+            &base_path,
+            r#"{"rules": [{"on": ".rs", "cmd": {"io": "rustfmt"}}]}"#,
+        )?;
+        wait_for_invalidation(&cache, &config_path);
+
+        assert_eq!(cache.config(&config_path)?.rules.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_cache_notices_cleaned_cache() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = fs::canonicalize(temp_dir.path())?;
+        let config_path = dir.join("foro.json");
+        let cache_dir = dir.join("cache");
+        fs::write(&config_path, r#"{"rules": []}"#)?;
+
+        let cache = ConfigCache::new();
+        assert!(cache.load(Some(&config_path), Some(&cache_dir)).is_err());
+
+        mark_ready(&*cache.config(&config_path)?, &cache_dir)?;
+        assert!(cache.load(Some(&config_path), Some(&cache_dir)).is_ok());
+
+        // e.g. `foro cache clean`
+        fs::remove_dir_all(&cache_dir)?;
+        assert!(cache.load(Some(&config_path), Some(&cache_dir)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_cache_does_not_cache_errors() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let config_path = fs::canonicalize(temp_dir.path())?.join("foro.json");
+        fs::write(&config_path, "{")?;
+
+        let cache = ConfigCache::new();
+        assert!(cache.config(&config_path).is_err());
+
+        fs::write(&config_path, r#"{"rules": []}"#)?;
+        assert!(cache.config(&config_path).is_ok());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! The framed protocol of the daemon socket.
+//!
+//! By default, a connection carries a single request: the client writes the JSON payload and
+//! half-closes the socket, then reads the JSON response until the daemon closes it.
+//!
+//! A connection that starts with [FRAMED_MAGIC] instead carries any number of requests.
+//! Each message is a frame: its length as a 4-byte big-endian integer, followed by that many
+//! bytes of JSON. Requests are [DaemonRequestFrame]s and responses are [DaemonResponseFrame]s
+//! with the id of their request. Requests may be pipelined, and their responses may come back
+//! in any order. A bulk format with `progress` gets progress events with its id before its
+//! response. A running request can be stopped with a `Cancel` request carrying its id, and the
+//! requests still running when the connection closes are cancelled.
+
+use crate::daemon::interface::{DaemonCommandPayload, DaemonResponse};
+use anyhow::{bail, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read, Write};
+
+/// Sent by the client first to use the framed protocol. It can't be the start of a JSON payload.
+pub const FRAMED_MAGIC: &[u8] = b"\0foro-framed-v1\n";
+
+/// Frames larger than this are rejected, so that a broken length doesn't allocate too much.
+const MAX_FRAME_LEN: usize = 1 << 30;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DaemonRequestFrame {
+    pub id: u64,
+    pub payload: DaemonCommandPayload,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DaemonResponseFrame {
+    pub id: u64,
+    pub response: DaemonResponse,
+}
+
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    let Ok(len) = u32::try_from(body.len()) else {
+        bail!("Frame too large: {} bytes", body.len());
+    };
+
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Read a frame, or `None` if the connection was closed between frames.
+pub fn read_frame<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<Option<T>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        bail!("Frame too large: {len} bytes");
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use crate::daemon::interface::{DaemonCommands, DaemonExecutionOptions};
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_frames_roundtrip() -> Result<()> {
+        let mut buf = Vec::new();
+
+        for id in [1, 2] {
+            write_frame(
+                &mut buf,
+                &DaemonRequestFrame {
+                    id,
+                    payload: DaemonCommandPayload {
+                        command: DaemonCommands::Ping,
+                        current_dir: PathBuf::from("/"),
+                        execution_options: DaemonExecutionOptions::default(),
+                    },
+                },
+            )?;
+        }
+
+        let mut reader = Cursor::new(buf);
+        let ids = std::iter::from_fn(|| read_frame::<_, DaemonRequestFrame>(&mut reader).unwrap())
+            .map(|frame| frame.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_frame_rejects_truncated_frame() {
+        let mut reader = Cursor::new([0, 0, 0, 10, b'{']);
+        assert!(read_frame::<_, serde_json::Value>(&mut reader).is_err());
+    }
+}
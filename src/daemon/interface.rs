@@ -2,25 +2,75 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DaemonFormatArgs {
     /// Path to format
     pub path: PathBuf,
     pub content: String,
+    /// Only report whether the file would change, without writing it
+    #[serde(default)]
+    pub check: bool,
+    /// Return a diff of the changes instead of writing the file
+    #[serde(default)]
+    pub diff: bool,
+    /// Return the formatted content instead of writing the file.
+    /// `path` is then only used for rule matching and plugin config discovery,
+    /// so it doesn't need to exist.
+    #[serde(default)]
+    pub return_content: bool,
+    /// Return the edits that format the content instead of writing the file.
+    /// Like `return_content`, `path` doesn't need to exist.
+    #[serde(default)]
+    pub return_edits: bool,
+    /// Only format this part of the content (e.g. an editor selection)
+    #[serde(default)]
+    pub range: Option<TargetRange>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A part of the content to format.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetRange {
+    /// Byte offsets, `end` is exclusive
+    Bytes { start: usize, end: usize },
+    /// 1-based line numbers, `end` is inclusive
+    Lines { start: usize, end: usize },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DaemonBulkFormatArgs {
     /// Paths to format
     pub paths: Vec<PathBuf>,
     /// Number of threads to use
     pub threads: usize,
+    /// Only report which files would change, without writing them
+    #[serde(default)]
+    pub check: bool,
+    /// Return diffs of the changes instead of writing the files
+    #[serde(default)]
+    pub diff: bool,
+    /// Send [BulkFormatProgress] events before the summary (framed protocol only)
+    #[serde(default)]
+    pub progress: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonWatchArgs {
+    /// Paths to start (or stop) watching. Empty to only list the watched paths.
+    pub paths: Vec<PathBuf>,
+    /// Stop watching the paths instead
+    #[serde(default)]
+    pub stop: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum DaemonCommands {
     Format(DaemonFormatArgs),
     BulkFormat(DaemonBulkFormatArgs),
+    Watch(DaemonWatchArgs),
+    /// Cancel the request with this id, sent earlier on the same framed connection
+    Cancel {
+        id: u64,
+    },
     Stop,
     Ping,
 }
@@ -40,20 +90,111 @@ pub struct DaemonCommandPayload {
     pub execution_options: DaemonExecutionOptions,
 }
 
+/// The changes formatting would make to a file, as unified diff hunks.
+///
+/// The `---`/`+++` header is rendered by the client.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    pub path: PathBuf,
+    pub hunks: String,
+}
+
+/// A position in the content, like an LSP `Position`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextPosition {
+    /// 0-based line
+    pub line: usize,
+    /// 0-based column in UTF-16 code units
+    pub character: usize,
+}
+
+/// A replacement of a part of the original content.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    /// Byte offset in the original content
+    pub start: usize,
+    /// Byte offset in the original content, exclusive
+    pub end: usize,
+    pub start_position: TextPosition,
+    pub end_position: TextPosition,
+    pub new_text: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DaemonFormatResponse {
     Success(),
-    Ignored(String), // Ignored with reason
+    Unformatted(PathBuf), // Would be changed (check mode only)
+    Diff(FileDiff),       // Would be changed (diff mode only)
+    Content(String),      // Formatted content (return_content mode only)
+    Edits(Vec<TextEdit>), // Edits from the content to the formatted one (return_edits mode only)
+    Ignored(String),      // Ignored with reason
     Error(String),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    /// Changed (or would be changed, in check and diff modes)
+    Changed,
+    Unchanged,
+    Ignored,
+    Error,
+}
+
+/// The outcome of formatting a single file in a bulk format.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileResult {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    /// The error message (errors only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct BulkFormatSummary {
     pub total_count: usize,
     pub changed_count: usize,
     pub unchanged_count: usize,
     pub ignored_count: usize,
     pub error_count: usize,
+    /// Diffs of the changed files (diff mode only)
+    #[serde(default)]
+    pub diffs: Vec<FileDiff>,
+    /// The outcome of each file, sorted by path
+    #[serde(default)]
+    pub files: Vec<FileResult>,
+    /// The bulk format was cancelled, so only the files done until then are counted
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
+/// Running totals of a bulk format.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BulkFormatTotals {
+    /// Files found so far, which grows while the paths are walked
+    pub found_count: usize,
+    pub changed_count: usize,
+    pub unchanged_count: usize,
+    pub ignored_count: usize,
+    pub error_count: usize,
+}
+
+impl BulkFormatTotals {
+    pub fn processed_count(&self) -> usize {
+        self.changed_count + self.unchanged_count + self.ignored_count + self.error_count
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum BulkFormatProgress {
+    /// A file started being formatted
+    Started(PathBuf),
+    /// A file is done, with the totals including it
+    Finished {
+        result: FileResult,
+        totals: BulkFormatTotals,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -62,10 +203,22 @@ pub enum DaemonBulkFormatResponse {
     Error(String),
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub enum DaemonWatchResponse {
+    /// All the paths being watched, after the request
+    Watching(Vec<PathBuf>),
+    Error(String),
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DaemonResponse {
     Format(DaemonFormatResponse),
     BulkFormat(DaemonBulkFormatResponse),
+    /// Sent before the response of a bulk format with `progress`
+    BulkFormatProgress(BulkFormatProgress),
+    Watch(DaemonWatchResponse),
+    /// Whether the request to cancel was still running
+    Cancel(bool),
     Stop,
     Pong(DaemonInfo),
 }
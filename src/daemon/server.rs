@@ -1,34 +1,44 @@
 use crate::app_dir::{AppDirResolver, DefaultAppDirResolver};
-use crate::bulk_format::{bulk_format, BulkFormatOption};
-use crate::config::{load_config_and_cache, read_config_bytes};
+use crate::bulk_format::{
+    bulk_format, BulkFormatControl, BulkFormatOption, ConfigResolver, ProgressCallback,
+};
+use crate::config::{resolve_config_file, FileHead};
 use crate::daemon::client::ping;
+use crate::daemon::config_cache::CONFIG_CACHE;
+use crate::daemon::framing::{
+    read_frame, write_frame, DaemonRequestFrame, DaemonResponseFrame, FRAMED_MAGIC,
+};
 use crate::daemon::interface::{
-    BulkFormatSummary, DaemonBulkFormatArgs, DaemonBulkFormatResponse, DaemonCommandPayload,
-    DaemonCommands, DaemonExecutionOptions, DaemonFormatArgs, DaemonFormatResponse, DaemonInfo,
-    DaemonResponse, DaemonSocketPath, OutputPath,
+    DaemonBulkFormatArgs, DaemonBulkFormatResponse, DaemonCommandPayload, DaemonCommands,
+    DaemonExecutionOptions, DaemonFormatArgs, DaemonFormatResponse, DaemonInfo, DaemonResponse,
+    DaemonSocketPath, DaemonWatchArgs, DaemonWatchResponse, FileDiff, OutputPath,
 };
 use crate::daemon::startup_lock::StartupLock;
 use crate::daemon::uds::{UnixListener, UnixStream};
+use crate::daemon::watch::FILE_WATCHER;
 use crate::debug_long;
+use crate::diff::{text_edits, unified_diff_hunks};
 use crate::handle_plugin::run::run;
-use crate::install_check::check_ready;
 use crate::log::IS_DAEMON_MAIN_THREAD;
 use crate::log::IS_DAEMON_PROCESS;
-use crate::path_utils::{normalize_path, to_wasm_path};
+use crate::path_utils::{canonicalize_allow_missing, normalize_path, to_wasm_path};
 use crate::process_utils::get_start_time;
+use crate::target_range::target_range_json;
 use anyhow::Result;
 use anyhow::{anyhow, Context};
 use foro_plugin_utils::data_json_utils::JsonGetter;
 use log::{debug, error, info, warn};
 use serde_json::json;
+use std::collections::HashMap;
 use std::fs::{DirBuilder, OpenOptions};
 use std::io::prelude::*;
-use std::io::{ErrorKind, Read};
+use std::io::{BufReader, ErrorKind, Read};
 use std::net::Shutdown;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
-use std::sync::{mpsc, OnceLock};
+use std::sync::{mpsc, Arc, Mutex, OnceLock, PoisonError};
 use std::thread::sleep;
 use std::time::Duration;
 use std::{fs, process, thread};
@@ -40,38 +50,45 @@ pub fn daemon_format_execute_with_args(
     current_dir: PathBuf,
     execution_options: DaemonExecutionOptions,
 ) -> Result<DaemonFormatResponse> {
-    let target_path = current_dir.join(&args.path).canonicalize()?;
+    let write = !args.check && !args.diff && !args.return_content && !args.return_edits;
+    // Without writing, the path is only used to select the rule (e.g. for stdin)
+    let target_path = if write {
+        current_dir.join(&args.path).canonicalize()?
+    } else {
+        canonicalize_allow_missing(&current_dir.join(&args.path))?
+    };
 
-    let config_bytes = read_config_bytes(execution_options.config_file.as_deref())?;
-    let (config, cache_dir) = load_config_and_cache(
+    let config_file = resolve_config_file(
         execution_options.config_file.as_deref(),
+        target_path.parent().unwrap_or(&current_dir),
+    );
+    let (config, cache_dir) = CONFIG_CACHE.load(
+        config_file.as_deref(),
         execution_options.cache_dir.as_deref(),
     )?;
-    check_ready(&config_bytes, &cache_dir)
-        .context("Plugins not installed: run `foro install` first")?;
 
-    let rule = match config.find_matched_rule(&target_path) {
-        Some(rule) => rule,
-        None => {
-            return Ok(DaemonFormatResponse::Ignored("No rule matched".to_string()));
-        }
-    };
+    let rules = config.find_matched_rules(&target_path, &FileHead::from_content(&args.content));
 
-    debug_long!("run rule: {:?}", rule);
-
-    let res = run(
-        &rule.cmd,
-        json!({
-            "wasm-current-dir":  to_wasm_path(&current_dir)?,
-            "os-current-dir": normalize_path(&current_dir)?,
-            "wasm-target": to_wasm_path(&target_path)?,
-            "os-target": normalize_path(&target_path)?,
-            "raw-target": args.path,
-            "target-content": args.content,
-        }),
-        &cache_dir,
-        true,
-    )?;
+    if rules.is_empty() {
+        return Ok(DaemonFormatResponse::Ignored("No rule matched".to_string()));
+    }
+
+    debug_long!("run rules: {:?}", rules);
+
+    let mut data_json = json!({
+        "wasm-current-dir":  to_wasm_path(&current_dir)?,
+        "os-current-dir": normalize_path(&current_dir)?,
+        "wasm-target": to_wasm_path(&target_path)?,
+        "os-target": normalize_path(&target_path)?,
+        "raw-target": args.path,
+        "target-content": &args.content,
+    });
+    if let Some(range) = &args.range {
+        let range = range.resolve(&args.content)?;
+        data_json["target-range"] = target_range_json(&args.content, &range);
+    }
+
+    let res = run(&rules, data_json, &cache_dir, true, write)?;
 
     if let Some(status) = String::get_value_opt(&res, ["format-status"]) {
         match status.as_str() {
@@ -88,6 +105,35 @@ pub fn daemon_format_execute_with_args(
         }
     }
 
+    if args.return_edits {
+        let edits = match String::get_value_opt(&res, ["formatted-content"]) {
+            Some(formatted) => text_edits(&args.content, &formatted),
+            None => Vec::new(),
+        };
+        return Ok(DaemonFormatResponse::Edits(edits));
+    }
+
+    if args.check || args.diff {
+        let formatted = String::get_value_opt(&res, ["formatted-content"])
+            .filter(|formatted| formatted != &args.content);
+
+        if let Some(formatted) = formatted {
+            if args.diff {
+                return Ok(DaemonFormatResponse::Diff(FileDiff {
+                    path: target_path,
+                    hunks: unified_diff_hunks(&args.content, &formatted),
+                }));
+            }
+
+            return Ok(DaemonFormatResponse::Unformatted(target_path));
+        }
+    }
+
+    if args.return_content {
+        let formatted = String::get_value_opt(&res, ["formatted-content"]).unwrap_or(args.content);
+        return Ok(DaemonFormatResponse::Content(formatted));
+    }
+
     Ok(DaemonFormatResponse::Success())
 }
 
@@ -95,6 +141,7 @@ pub fn daemon_bulk_format_execute_with_args(
     args: DaemonBulkFormatArgs,
     current_dir: PathBuf,
     execution_options: DaemonExecutionOptions,
+    mut control: BulkFormatControl,
 ) -> Result<DaemonBulkFormatResponse> {
     let paths = args
         .paths
@@ -107,32 +154,69 @@ pub fn daemon_bulk_format_execute_with_args(
         })
         .collect::<Result<Vec<PathBuf>>>()?;
 
-    let config_bytes = read_config_bytes(execution_options.config_file.as_deref())?;
-    let (config, cache_dir) = load_config_and_cache(
-        execution_options.config_file.as_deref(),
+    // The config of the current directory decides the cache directory,
+    // and each file is formatted with the config of the nearest project.
+    let config_file = resolve_config_file(execution_options.config_file.as_deref(), &current_dir);
+    let (config, cache_dir) = CONFIG_CACHE.load(
+        config_file.as_deref(),
         execution_options.cache_dir.as_deref(),
     )?;
-    check_ready(&config_bytes, &cache_dir)
-        .context("Plugins not installed: run `foro install` first")?;
+
+    let configs = ConfigResolver::new(execution_options.config_file, &cache_dir);
+    configs.insert(config_file, config);
 
     let opt = BulkFormatOption {
         paths,
         threads: args.threads,
         use_default_ignore: true,
         current_dir,
+        check: args.check,
+        diff: args.diff,
     };
 
-    let summary = bulk_format(&opt, &config, &cache_dir, true)?;
-    Ok(DaemonBulkFormatResponse::Success(BulkFormatSummary {
-        total_count: summary.processed_count(),
-        changed_count: summary.changed_count,
-        unchanged_count: summary.unchanged_count,
-        ignored_count: summary.ignored_count,
-        error_count: summary.error_count,
-    }))
+    if !args.progress {
+        control.on_progress = None;
+    }
+
+    let summary = bulk_format(&opt, Arc::new(configs), &cache_dir, true, control)?;
+    Ok(DaemonBulkFormatResponse::Success(summary.into()))
 }
 
-pub fn serverside_exec_command(payload: DaemonCommandPayload) -> DaemonResponse {
+pub fn daemon_watch_execute_with_args(
+    args: DaemonWatchArgs,
+    current_dir: PathBuf,
+    execution_options: DaemonExecutionOptions,
+) -> Result<DaemonWatchResponse> {
+    let paths = args
+        .paths
+        .iter()
+        .map(|p| {
+            current_dir
+                .join(p)
+                .canonicalize()
+                .map_err(anyhow::Error::from)
+        })
+        .collect::<Result<Vec<PathBuf>>>()?;
+
+    if args.stop {
+        FILE_WATCHER.unwatch(&paths);
+    } else {
+        FILE_WATCHER.watch(
+            &paths,
+            execution_options.config_file.as_deref(),
+            execution_options.cache_dir.as_deref(),
+        )?;
+    }
+
+    Ok(DaemonWatchResponse::Watching(FILE_WATCHER.watched_paths()))
+}
+
+/// Run a command in the daemon. `control` follows and stops a bulk format, if the
+/// connection supports it.
+pub fn serverside_exec_command(
+    payload: DaemonCommandPayload,
+    control: BulkFormatControl,
+) -> DaemonResponse {
     match payload.command {
         DaemonCommands::Format(s_args) => {
             let res = daemon_format_execute_with_args(
@@ -151,6 +235,7 @@ pub fn serverside_exec_command(payload: DaemonCommandPayload) -> DaemonResponse
                 s_args,
                 payload.current_dir,
                 payload.execution_options,
+                control,
             );
 
             match res {
@@ -160,6 +245,20 @@ pub fn serverside_exec_command(payload: DaemonCommandPayload) -> DaemonResponse
                 }
             }
         }
+        DaemonCommands::Watch(s_args) => {
+            let res = daemon_watch_execute_with_args(
+                s_args,
+                payload.current_dir,
+                payload.execution_options,
+            );
+
+            match res {
+                Ok(res) => DaemonResponse::Watch(res),
+                Err(err) => DaemonResponse::Watch(DaemonWatchResponse::Error(format!("{err:#}"))),
+            }
+        }
+        // A single request connection has no other request to cancel
+        DaemonCommands::Cancel { .. } => DaemonResponse::Cancel(false),
         DaemonCommands::Stop => DaemonResponse::Stop,
         DaemonCommands::Ping => DaemonResponse::Pong(DAEMON_INFO.get().unwrap().clone()),
     }
@@ -197,8 +296,141 @@ fn read_stream_with_retry(stream: &mut UnixStream, buf: &mut Vec<u8>) -> Result<
     Ok(())
 }
 
+/// Serve a connection of the framed protocol, until the client closes it.
+///
+/// Each request is run on its own thread, so that a slow request doesn't hold back
+/// the ones pipelined after it. Running requests can be cancelled by id, and are cancelled
+/// when the client goes away.
+fn handle_framed_client(stream: UnixStream, stop_sender: Sender<()>) -> Result<()> {
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut reader = BufReader::new(stream);
+    let running: Arc<Mutex<HashMap<u64, Arc<AtomicBool>>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    let res = serve_framed_requests(&mut reader, &writer, &running, &stop_sender);
+
+    // The running requests hold the writer, so the connection is closed explicitly
+    if res.is_err() {
+        let writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = writer.shutdown(Shutdown::Both);
+    }
+
+    for cancelled in running.lock().unwrap().values() {
+        cancelled.store(true, Ordering::SeqCst);
+    }
+
+    res
+}
+
+fn serve_framed_requests(
+    reader: &mut BufReader<UnixStream>,
+    writer: &Arc<Mutex<UnixStream>>,
+    running: &Arc<Mutex<HashMap<u64, Arc<AtomicBool>>>>,
+    stop_sender: &Sender<()>,
+) -> Result<()> {
+    while let Some(frame) = read_frame::<_, DaemonRequestFrame>(reader)? {
+        debug_long!("Received: {:?}", &frame);
+
+        if let DaemonCommands::Cancel { id } = frame.payload.command {
+            let found = match running.lock().unwrap().get(&id) {
+                Some(cancelled) => {
+                    cancelled.store(true, Ordering::SeqCst);
+                    true
+                }
+                None => false,
+            };
+            info!("Cancel request {id} (running: {found})");
+
+            write_frame(
+                &mut *writer.lock().unwrap(),
+                &DaemonResponseFrame {
+                    id: frame.id,
+                    response: DaemonResponse::Cancel(found),
+                },
+            )?;
+            continue;
+        }
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        running.lock().unwrap().insert(frame.id, cancelled.clone());
+
+        let writer = writer.clone();
+        let running = running.clone();
+        let stop_sender = stop_sender.clone();
+
+        thread::spawn(move || {
+            let on_progress: ProgressCallback = {
+                let writer = writer.clone();
+                Arc::new(move |event| {
+                    let res = write_frame(
+                        &mut *writer.lock().unwrap(),
+                        &DaemonResponseFrame {
+                            id: frame.id,
+                            response: DaemonResponse::BulkFormatProgress(event),
+                        },
+                    );
+                    if let Err(err) = res {
+                        debug!("Failed to send progress: {err:#}");
+                    }
+                })
+            };
+
+            let control = BulkFormatControl {
+                on_progress: Some(on_progress),
+                cancelled,
+            };
+            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                serverside_exec_command(frame.payload, control)
+            }));
+            running.lock().unwrap().remove(&frame.id);
+
+            let Ok(response) = res else {
+                // The client would wait for the response forever, so the connection is closed
+                error!("Request {} panicked, closing the connection", frame.id);
+                let writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+                let _ = writer.shutdown(Shutdown::Both);
+                return;
+            };
+            let is_stop = matches!(response, DaemonResponse::Stop);
+
+            debug_long!("Response: {:?}", &response);
+
+            let res = write_frame(
+                &mut *writer.lock().unwrap(),
+                &DaemonResponseFrame {
+                    id: frame.id,
+                    response,
+                },
+            );
+            if let Err(err) = res {
+                warn!("Failed to send response: {err:#}");
+            }
+
+            if is_stop {
+                let _ = stop_sender.send(());
+            }
+        });
+    }
+
+    Ok(())
+}
+
 fn handle_client(mut stream: UnixStream, stop_sender: Sender<()>) -> Result<()> {
-    let mut buf = Vec::new();
+    // The accepted stream inherits the non-blocking mode of the listener on some platforms
+    stream.set_nonblocking(false)?;
+
+    let mut buf = vec![0; 1];
+    stream.read_exact(&mut buf)?;
+
+    if buf == FRAMED_MAGIC[..1] {
+        let mut magic = vec![0; FRAMED_MAGIC.len() - 1];
+        stream.read_exact(&mut magic)?;
+        if magic != FRAMED_MAGIC[1..] {
+            return Err(anyhow!("Invalid framed protocol header"));
+        }
+
+        return handle_framed_client(stream, stop_sender);
+    }
+
     read_stream_with_retry(&mut stream, &mut buf)?;
 
     log::trace!("{:?}", String::from_utf8_lossy(&buf));
@@ -210,7 +442,7 @@ fn handle_client(mut stream: UnixStream, stop_sender: Sender<()>) -> Result<()>
 
     debug_long!("Received: {:?}", &payload);
 
-    let response = serverside_exec_command(payload);
+    let response = serverside_exec_command(payload, BulkFormatControl::default());
 
     debug_long!("Response: {:?}", &response);
 
@@ -314,8 +546,11 @@ pub fn daemon_main(socket: WrappedUnixSocket) {
                 let t_tx = tx.clone();
                 thread::spawn(move || {
                     debug!("New client connected");
-                    handle_client(stream, t_tx).unwrap();
-                    debug!("Client exited");
+                    // The input comes from the client, so a bad request only drops its connection
+                    match handle_client(stream, t_tx) {
+                        Ok(()) => debug!("Client exited"),
+                        Err(err) => error!("Dropped client connection: {err:#}"),
+                    }
                 });
             }
             Err(err) if err.kind() == ErrorKind::WouldBlock => {}
@@ -0,0 +1,485 @@
+use crate::bulk_format::{default_ignore_overrides, format_file, FormatFileOutcome};
+use crate::config::resolve_config_file;
+use crate::daemon::config_cache::CONFIG_CACHE;
+use anyhow::{anyhow, Context, Result};
+use ignore::overrides::Override;
+use ignore::WalkBuilder;
+use log::{debug, error, info, trace, warn};
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The paths watched by the daemon, shared by all requests.
+pub static FILE_WATCHER: LazyLock<FileWatcher> = LazyLock::new(FileWatcher::new);
+
+/// How long events must stop before the changed files are formatted.
+/// Editors often save in several steps (e.g. write a temporary file and rename it).
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+struct WatchRoot {
+    config_file: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    overrides: Override,
+}
+
+#[derive(Default)]
+struct State {
+    roots: BTreeMap<PathBuf, WatchRoot>,
+    /// The state of the files foro has written, to skip the events caused by its own writes.
+    written: HashMap<PathBuf, (SystemTime, u64)>,
+    /// Directories with an OS watch.
+    watched_dirs: BTreeSet<PathBuf>,
+}
+
+impl State {
+    /// The innermost watched root containing `path`.
+    fn root_of(&self, path: &Path) -> Option<(&PathBuf, &WatchRoot)> {
+        self.roots
+            .iter()
+            .rev()
+            .find(|(root, _)| path.starts_with(root))
+    }
+
+    /// Watch `dir` and the directories under it that aren't ignored, each on its own,
+    /// so that ignored trees (e.g. `node_modules` or `target`) don't use up the OS limit
+    /// of watches. Returns the files found in the new directories.
+    fn watch_dirs(
+        &mut self,
+        watcher: &mut RecommendedWatcher,
+        overrides: &Override,
+        dir: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+
+        for entry in walk_builder(dir, overrides).build() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    warn!("Failed to walk {dir:?}: {err}");
+                    continue;
+                }
+            };
+            let path = entry.into_path();
+
+            if !path.is_dir() {
+                files.push(path);
+                continue;
+            }
+            if self.watched_dirs.contains(&path) {
+                continue;
+            }
+
+            watcher
+                .watch(&path, RecursiveMode::NonRecursive)
+                .with_context(|| {
+                    format!(
+                        "Failed to watch {path:?}, the OS limit of watches may be reached \
+                         (e.g. `fs.inotify.max_user_watches` on Linux)"
+                    )
+                })?;
+            self.watched_dirs.insert(path);
+        }
+
+        Ok(files)
+    }
+}
+
+/// A walker of `dir` that skips what `foro format <dir>` does, including the ignore
+/// files of the parent directories.
+fn walk_builder(dir: &Path, overrides: &Override) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(dir);
+    builder
+        .add_custom_ignore_filename(".foro-ignore")
+        .overrides(overrides.clone());
+    builder
+}
+
+/// Whether a path under `root` is skipped, like it is when formatting the directory.
+///
+/// The walker only descends towards `path`, so that the ignore rules (and their precedence)
+/// are exactly those of [`State::watch_dirs`] and `foro format`.
+fn is_ignored(root: &Path, overrides: &Override, path: &Path) -> bool {
+    if !path.starts_with(root) {
+        return true;
+    }
+
+    let target = path.to_path_buf();
+    let mut builder = walk_builder(root, overrides);
+    builder.filter_entry(move |entry| target.starts_with(entry.path()));
+
+    !builder
+        .build()
+        .filter_map(Result::ok)
+        .any(|entry| entry.path() == path)
+}
+
+/// Watches registered paths and formats the files that change in them.
+pub struct FileWatcher {
+    state: Arc<Mutex<State>>,
+    watcher: Arc<Mutex<Option<RecommendedWatcher>>>,
+}
+
+impl FileWatcher {
+    fn new() -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let (tx, rx) = mpsc::channel();
+
+        let watcher = notify::recommended_watcher(move |event| send_changed_paths(event, &tx))
+            .inspect_err(|err| error!("Failed to create file watcher: {err}"))
+            .ok();
+        let watcher = Arc::new(Mutex::new(watcher));
+
+        let worker_state = state.clone();
+        let worker_watcher = watcher.clone();
+        thread::spawn(move || format_changed_files(worker_state, worker_watcher, rx));
+
+        Self { state, watcher }
+    }
+
+    /// Start watching `paths`. Files in them are formatted with the given config
+    /// (or their nearest project config) when they change.
+    pub fn watch(
+        &self,
+        paths: &[PathBuf],
+        config_file: Option<&Path>,
+        cache_dir: Option<&Path>,
+    ) -> Result<()> {
+        let mut watcher = self.watcher.lock().unwrap();
+        let watcher = watcher
+            .as_mut()
+            .ok_or_else(|| anyhow!("File watching is not available"))?;
+
+        for path in paths {
+            let overrides = default_ignore_overrides(path);
+            let mut state = self.state.lock().unwrap();
+
+            if !state.roots.contains_key(path) {
+                state.watch_dirs(watcher, &overrides, path)?;
+            }
+
+            info!("Watching: {path:?}");
+
+            state.roots.insert(
+                path.clone(),
+                WatchRoot {
+                    config_file: config_file.map(Path::to_path_buf),
+                    cache_dir: cache_dir.map(Path::to_path_buf),
+                    overrides,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn unwatch(&self, paths: &[PathBuf]) {
+        let mut watcher = self.watcher.lock().unwrap();
+
+        for path in paths {
+            let mut state = self.state.lock().unwrap();
+            if state.roots.remove(path).is_none() {
+                continue;
+            }
+
+            info!("Stopped watching: {path:?}");
+
+            // Directories that are also under another root stay watched
+            let dirs: Vec<_> = state
+                .watched_dirs
+                .iter()
+                .filter(|dir| dir.starts_with(path) && state.root_of(dir).is_none())
+                .cloned()
+                .collect();
+
+            for dir in dirs {
+                state.watched_dirs.remove(&dir);
+
+                // Events outside the remaining roots are skipped anyway,
+                // so a failure to remove the OS watch doesn't matter much
+                if let Some(Err(err)) = watcher.as_mut().map(|watcher| watcher.unwatch(&dir)) {
+                    warn!("Failed to unwatch {dir:?}: {err}");
+                }
+            }
+        }
+    }
+
+    pub fn watched_paths(&self) -> Vec<PathBuf> {
+        self.state.lock().unwrap().roots.keys().cloned().collect()
+    }
+}
+
+fn send_changed_paths(event: notify::Result<Event>, tx: &Sender<PathBuf>) {
+    let event = match event {
+        Ok(event) => event,
+        Err(err) => {
+            warn!("File watcher error: {err}");
+            return;
+        }
+    };
+
+    // Removals are passed on to drop the watches of removed directories
+    match event.kind {
+        EventKind::Modify(ModifyKind::Metadata(_)) => return,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {}
+        _ => return,
+    }
+
+    for path in event.paths {
+        let _ = tx.send(path);
+    }
+}
+
+fn format_changed_files(
+    state: Arc<Mutex<State>>,
+    watcher: Arc<Mutex<Option<RecommendedWatcher>>>,
+    rx: Receiver<PathBuf>,
+) {
+    while let Ok(path) = rx.recv() {
+        let mut changed = BTreeSet::from([path]);
+
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(path) => {
+                    changed.insert(path);
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        debug!("Changed files: {changed:?}");
+
+        for path in changed {
+            if path.is_dir() {
+                for file in watch_new_dir(&state, &watcher, &path) {
+                    format_changed_file(&state, &file);
+                }
+            } else {
+                format_changed_file(&state, &path);
+            }
+        }
+    }
+}
+
+fn file_state(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Watch a directory created under a root, unless it's ignored. Returns the files
+/// already in it, since they may have been created before the watch.
+fn watch_new_dir(
+    state: &Mutex<State>,
+    watcher: &Mutex<Option<RecommendedWatcher>>,
+    dir: &Path,
+) -> Vec<PathBuf> {
+    let mut watcher = watcher.lock().unwrap();
+    let Some(watcher) = watcher.as_mut() else {
+        return Vec::new();
+    };
+    let mut state = state.lock().unwrap();
+
+    if state.watched_dirs.contains(dir) {
+        return Vec::new();
+    }
+    let Some((root, watch_root)) = state.root_of(dir) else {
+        return Vec::new();
+    };
+    let root = root.clone();
+    let overrides = watch_root.overrides.clone();
+
+    if is_ignored(&root, &overrides, dir) {
+        trace!("Ignored new directory: {dir:?}");
+        return Vec::new();
+    }
+
+    debug!("Watching new directory: {dir:?}");
+
+    state
+        .watch_dirs(watcher, &overrides, dir)
+        .unwrap_or_else(|err| {
+            error!("{err:#}");
+            Vec::new()
+        })
+}
+
+fn format_changed_file(state: &Mutex<State>, path: &Path) {
+    let (config_file, cache_dir) = {
+        let mut state = state.lock().unwrap();
+
+        if !path.is_file() {
+            state.watched_dirs.remove(path);
+            return;
+        }
+
+        if let Some(written) = state.written.remove(path) {
+            if file_state(path) == Some(written) {
+                trace!("Skipping own write: {path:?}");
+                return;
+            }
+        }
+
+        let Some((root, watch_root)) = state.root_of(path) else {
+            return;
+        };
+        let root = root.clone();
+        let overrides = watch_root.overrides.clone();
+        let config_file = watch_root.config_file.clone();
+        let cache_dir = watch_root.cache_dir.clone();
+
+        if is_ignored(&root, &overrides, path) {
+            trace!("Ignored change: {path:?}");
+            return;
+        }
+
+        (config_file, cache_dir)
+    };
+
+    let dir = path.parent().unwrap();
+    let config_file = resolve_config_file(config_file.as_deref(), dir);
+
+    let res = CONFIG_CACHE
+        .load(config_file.as_deref(), cache_dir.as_deref())
+        .and_then(|(config, cache_dir)| {
+            format_file(path, dir, &config, &cache_dir, true, None, false, false)
+        });
+
+    match res {
+        Ok(FormatFileOutcome::Changed(_)) => {
+            info!("Formatted on change: {path:?}");
+
+            if let Some(file) = file_state(path) {
+                state
+                    .lock()
+                    .unwrap()
+                    .written
+                    .insert(path.to_path_buf(), file);
+            }
+        }
+        Ok(_) => {}
+        Err(err) => error!("Error formatting file {}: {err:#}", path.display()),
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_ignored() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = fs::canonicalize(temp_dir.path())?;
+
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join(".gitignore"), "*.log\n")?;
+        fs::write(root.join("src/.foro-ignore"), "generated/\n!keep.log\n")?;
+        for file in [
+            ".git/HEAD",
+            "main.rs",
+            "debug.log",
+            "src/main.rs",
+            "src/keep.log",
+            "src/.main.rs.swp",
+            "src/generated/schema.rs",
+            "node_modules/pkg/index.js",
+        ] {
+            fs::create_dir_all(root.join(file).parent().unwrap())?;
+            fs::write(root.join(file), "")?;
+        }
+
+        let overrides = default_ignore_overrides(&root);
+        let is_ignored = |path: &str| is_ignored(&root, &overrides, &root.join(path));
+
+        assert!(!is_ignored("main.rs"));
+        assert!(!is_ignored("src/main.rs"));
+        assert!(is_ignored("debug.log"));
+        assert!(is_ignored("src/generated/schema.rs"));
+        assert!(!is_ignored("src/keep.log"));
+        assert!(is_ignored("node_modules/pkg/index.js"));
+        assert!(is_ignored(".git/HEAD"));
+        assert!(is_ignored("src/.main.rs.swp"));
+
+        assert!(is_ignored("node_modules"));
+        assert!(is_ignored("src/generated"));
+        assert!(!is_ignored("src"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_ignored_reads_parent_ignore_files() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let parent = fs::canonicalize(temp_dir.path())?;
+        let root = parent.join("project");
+
+        fs::create_dir(&root)?;
+        fs::write(parent.join(".foro-ignore"), "*.txt\n")?;
+        fs::write(root.join("a.txt"), "")?;
+        fs::write(root.join("a.rs"), "")?;
+
+        let overrides = default_ignore_overrides(&root);
+
+        // Like `foro format`, ignore files above the root apply too
+        assert!(is_ignored(&root, &overrides, &root.join("a.txt")));
+        assert!(!is_ignored(&root, &overrides, &root.join("a.rs")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_watch_dirs_skips_ignored_dirs() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = fs::canonicalize(temp_dir.path())?;
+
+        fs::create_dir_all(root.join("src/generated"))?;
+        fs::create_dir_all(root.join("node_modules/pkg"))?;
+        fs::create_dir_all(root.join(".git/objects"))?;
+        fs::write(root.join("src/.foro-ignore"), "generated/\n")?;
+        fs::write(root.join("src/main.rs"), "")?;
+
+        let mut watcher = notify::recommended_watcher(|_| {})?;
+        let mut state = State::default();
+
+        let files = state.watch_dirs(&mut watcher, &default_ignore_overrides(&root), &root)?;
+
+        assert_eq!(
+            state.watched_dirs,
+            BTreeSet::from([root.clone(), root.join("src")])
+        );
+        assert_eq!(files, [root.join("src/main.rs")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_root_of_prefers_innermost_root() {
+        let mut state = State::default();
+
+        for root in ["/project", "/project/sub"] {
+            state.roots.insert(
+                PathBuf::from(root),
+                WatchRoot {
+                    config_file: None,
+                    cache_dir: None,
+                    overrides: Override::empty(),
+                },
+            );
+        }
+
+        let root_of = |path: &str| state.root_of(Path::new(path)).map(|(root, _)| root.clone());
+
+        assert_eq!(
+            root_of("/project/sub/a.rs"),
+            Some(PathBuf::from("/project/sub"))
+        );
+        assert_eq!(root_of("/project/a.rs"), Some(PathBuf::from("/project")));
+        assert_eq!(root_of("/other/a.rs"), None);
+    }
+}
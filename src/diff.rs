@@ -0,0 +1,375 @@
+use crate::daemon::interface::{TextEdit, TextPosition};
+use similar::{DiffTag, TextDiff};
+use std::ops::Range;
+use std::time::Duration;
+
+/// Build the hunks of a unified diff between `original` and `formatted`.
+///
+/// The `---`/`+++` header is not included, because the daemon doesn't know
+/// how the client wants to display the path. See [render_unified_diff].
+pub fn unified_diff_hunks(original: &str, formatted: &str) -> String {
+    TextDiff::from_lines(original, formatted)
+        .unified_diff()
+        .context_radius(3)
+        .to_string()
+}
+
+/// Prepend a git-style header to the hunks built by [unified_diff_hunks].
+pub fn render_unified_diff(path: &str, hunks: &str) -> String {
+    format!("--- a/{path}\n+++ b/{path}\n{hunks}")
+}
+
+/// The byte offsets where the lines of `content` start, followed by the length of `content`.
+///
+/// The lines are split like [TextDiff::from_lines] does, so line `i` of a diff
+/// is `content[starts[i]..starts[i + 1]]`.
+pub fn line_starts(content: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    starts.extend(
+        content
+            .match_indices('\n')
+            .map(|(i, _)| i + 1)
+            .filter(|&i| i < content.len()),
+    );
+    starts.push(content.len());
+    starts
+}
+
+/// Whether a change of `change` (byte offsets in the original) touches `range`.
+/// Empty ranges are cursor positions and insertions, which touch what they're in.
+fn overlaps(change: &Range<usize>, range: &Range<usize>) -> bool {
+    match (change.is_empty(), range.is_empty()) {
+        (false, false) => change.start < range.end && range.start < change.end,
+        (false, true) => change.contains(&range.start),
+        (true, false) => range.contains(&change.start),
+        (true, true) => change.start == range.start,
+    }
+}
+
+/// Apply only the changes from `original` to `formatted` that touch `range` (byte offsets
+/// in `original`), leaving the rest of `original` as is.
+///
+/// Changes are made of whole lines, so a change that touches the range is applied entirely.
+/// Multi-line changes are only split when every line is replaced by exactly one line.
+/// Returns the result and `range` moved onto it.
+pub fn apply_changes_in_range(
+    original: &str,
+    formatted: &str,
+    range: Range<usize>,
+) -> (String, Range<usize>) {
+    let diff = TextDiff::from_lines(original, formatted);
+    let old_starts = line_starts(original);
+    let new_starts = line_starts(formatted);
+
+    // A replacement of as many lines as it replaces is split into single lines,
+    // since formatters often change every line of a block
+    let lines = diff.ops().iter().flat_map(|op| {
+        let (old, new) = (op.old_range(), op.new_range());
+        let changed = op.tag() != DiffTag::Equal;
+
+        if op.tag() == DiffTag::Replace && old.len() == new.len() {
+            old.zip(new)
+                .map(|(old, new)| (old..old + 1, new..new + 1, changed))
+                .collect::<Vec<_>>()
+        } else {
+            vec![(old, new, changed)]
+        }
+    });
+
+    let mut result = String::with_capacity(original.len());
+    let mut start = None;
+    let mut end = (range.end == 0).then_some(0);
+
+    for (old, new, changed) in lines {
+        let old = old_starts[old.start]..old_starts[old.end];
+        let new = new_starts[new.start]..new_starts[new.end];
+
+        let applied = changed && overlaps(&old, &range);
+        let text = if applied {
+            &formatted[new]
+        } else {
+            &original[old.clone()]
+        };
+        let offset = result.len();
+
+        if start.is_none() && old.contains(&range.start) {
+            start = Some(if applied {
+                offset
+            } else {
+                offset + range.start - old.start
+            });
+        }
+        if end.is_none() && old.start < range.end && range.end <= old.end {
+            end = Some(if applied {
+                offset + text.len()
+            } else {
+                offset + range.end - old.start
+            });
+        }
+
+        result.push_str(text);
+    }
+
+    let start = start.unwrap_or(result.len());
+    let end = if range.is_empty() {
+        start
+    } else {
+        end.unwrap_or(result.len()).max(start)
+    };
+
+    (result, start..end)
+}
+
+/// How long diffing the characters of changed lines may take, before settling
+/// for a less minimal (but still correct) list of edits.
+const CHAR_DIFF_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Converts byte offsets into LSP-like positions.
+struct Positions<'a> {
+    content: &'a str,
+    newlines: Vec<usize>,
+}
+
+impl<'a> Positions<'a> {
+    fn new(content: &'a str) -> Self {
+        Self {
+            content,
+            newlines: content.match_indices('\n').map(|(i, _)| i).collect(),
+        }
+    }
+
+    fn at(&self, offset: usize) -> TextPosition {
+        let line = self.newlines.partition_point(|&i| i < offset);
+        let line_start = match line {
+            0 => 0,
+            _ => self.newlines[line - 1] + 1,
+        };
+
+        TextPosition {
+            line,
+            character: self.content[line_start..offset].encode_utf16().count(),
+        }
+    }
+}
+
+/// The byte offsets where the chars of `s` start, followed by the length of `s`.
+fn char_starts(s: &str) -> Vec<usize> {
+    s.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(s.len()))
+        .collect()
+}
+
+/// Append the edits that turn `original[old]` into `formatted[new]`, diffing by characters.
+fn push_char_edits(
+    edits: &mut Vec<(Range<usize>, String)>,
+    original: &str,
+    formatted: &str,
+    old: Range<usize>,
+    new: Range<usize>,
+) {
+    let (old_text, new_text) = (&original[old.clone()], &formatted[new]);
+    let diff = TextDiff::configure()
+        .timeout(CHAR_DIFF_TIMEOUT)
+        .diff_chars(old_text, new_text);
+    let old_chars = char_starts(old_text);
+    let new_chars = char_starts(new_text);
+
+    for op in diff.ops() {
+        if op.tag() == DiffTag::Equal {
+            continue;
+        }
+
+        let start = old.start + old_chars[op.old_range().start];
+        let end = old.start + old_chars[op.old_range().end];
+        let text = &new_text[new_chars[op.new_range().start]..new_chars[op.new_range().end]];
+
+        match edits.last_mut() {
+            // A replacement is reported as a deletion and an insertion at the same place
+            Some((last, last_text)) if last.end == start => {
+                last.end = end;
+                last_text.push_str(text);
+            }
+            _ => edits.push((start..end, text.to_string())),
+        }
+    }
+}
+
+/// The edits that turn `original` into `formatted`, in order and without overlaps.
+///
+/// Changed lines are found first and then diffed by characters, so that an edit
+/// only covers what actually changed in them.
+pub fn text_edits(original: &str, formatted: &str) -> Vec<TextEdit> {
+    let diff = TextDiff::from_lines(original, formatted);
+    let old_starts = line_starts(original);
+    let new_starts = line_starts(formatted);
+
+    let mut edits = Vec::new();
+    let mut changed: Option<(Range<usize>, Range<usize>)> = None;
+
+    for op in diff.ops() {
+        let old = old_starts[op.old_range().start]..old_starts[op.old_range().end];
+        let new = new_starts[op.new_range().start]..new_starts[op.new_range().end];
+
+        if op.tag() == DiffTag::Equal {
+            if let Some((old, new)) = changed.take() {
+                push_char_edits(&mut edits, original, formatted, old, new);
+            }
+        } else {
+            changed = Some(match changed {
+                Some((changed_old, changed_new)) => {
+                    (changed_old.start..old.end, changed_new.start..new.end)
+                }
+                None => (old, new),
+            });
+        }
+    }
+    if let Some((old, new)) = changed {
+        push_char_edits(&mut edits, original, formatted, old, new);
+    }
+
+    let positions = Positions::new(original);
+
+    edits
+        .into_iter()
+        .map(|(range, new_text)| TextEdit {
+            start: range.start,
+            end: range.end,
+            start_position: positions.at(range.start),
+            end_position: positions.at(range.end),
+            new_text,
+        })
+        .collect()
+}
+
+/// Apply edits built by [text_edits] to `original`.
+#[cfg(test)]
+fn apply_text_edits(original: &str, edits: &[TextEdit]) -> String {
+    let mut result = original.to_string();
+    for edit in edits.iter().rev() {
+        result.replace_range(edit.start..edit.end, &edit.new_text);
+    }
+    result
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff_hunks_no_changes() {
+        assert_eq!(unified_diff_hunks("a\nb\n", "a\nb\n"), "");
+    }
+
+    #[test]
+    fn test_render_unified_diff() {
+        let hunks = unified_diff_hunks("fn main( ) {}\n", "fn main() {}\n");
+        assert_eq!(
+            render_unified_diff("src/main.rs", &hunks),
+            "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1 +1 @@\n-fn main( ) {}\n+fn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_line_starts() {
+        assert_eq!(line_starts(""), vec![0, 0]);
+        assert_eq!(line_starts("a\nb\n"), vec![0, 2, 4]);
+        assert_eq!(line_starts("a\nb"), vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn test_apply_changes_in_range() {
+        let original = "a( )\nb( )\nc( )\n";
+        let formatted = "a()\nb()\nc()\n";
+
+        // Only the selected line is changed, and the range follows it
+        let (result, range) = apply_changes_in_range(original, formatted, 5..10);
+        assert_eq!(result, "a( )\nb()\nc( )\n");
+        assert_eq!(range, 5..9);
+
+        // A cursor position selects the line it's in
+        let (result, range) = apply_changes_in_range(original, formatted, 1..1);
+        assert_eq!(result, "a()\nb( )\nc( )\n");
+        assert_eq!(range, 0..0);
+
+        // A range ending at the start of a line doesn't include that line
+        let (result, _) = apply_changes_in_range(original, formatted, 0..5);
+        assert_eq!(result, "a()\nb( )\nc( )\n");
+    }
+
+    #[test]
+    fn test_apply_changes_in_range_keeps_unchanged_offsets() {
+        let original = "keep\nfix( )\nkeep\n";
+        let formatted = "keep\nfix()\nkeep\nadded\n";
+
+        let (result, range) = apply_changes_in_range(original, formatted, 2..8);
+        assert_eq!(result, "keep\nfix()\nkeep\n");
+        assert_eq!(range, 2..11);
+    }
+
+    #[test]
+    fn test_text_edits() {
+        let original = "fn main( ) {\n    let x=1;\n}\n";
+        let formatted = "fn main() {\n    let x = 1;\n}\n";
+
+        let edits = text_edits(original, formatted);
+        assert_eq!(
+            edits[0],
+            TextEdit {
+                start: 8,
+                end: 9,
+                start_position: TextPosition {
+                    line: 0,
+                    character: 8
+                },
+                end_position: TextPosition {
+                    line: 0,
+                    character: 9
+                },
+                new_text: "".to_string(),
+            }
+        );
+        // Only the spaces around `=` are inserted, not the whole line
+        assert!(edits[1..]
+            .iter()
+            .all(|edit| edit.start_position.line == 1 && edit.end - edit.start <= 1));
+        assert_eq!(apply_text_edits(original, &edits), formatted);
+
+        assert_eq!(text_edits(formatted, formatted), vec![]);
+    }
+
+    #[test]
+    fn test_text_edits_positions_count_utf16() {
+        let original = "\"😀\",1\n";
+        let formatted = "\"😀\", 1\n";
+
+        let edits = text_edits(original, formatted);
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].start, 7);
+        assert_eq!(
+            edits[0].start_position,
+            TextPosition {
+                line: 0,
+                character: 5
+            }
+        );
+        assert_eq!(apply_text_edits(original, &edits), formatted);
+    }
+
+    #[test]
+    fn test_text_edits_added_and_removed_lines() {
+        let original = "a\nb\n\n\nc";
+        let formatted = "a\nb\n\nc\n";
+
+        let edits = text_edits(original, formatted);
+        assert_eq!(apply_text_edits(original, &edits), formatted);
+        assert_eq!(
+            text_edits("", "a\n")[0].end_position,
+            TextPosition {
+                line: 0,
+                character: 0
+            }
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn temp_path_for(path: &Path) -> Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Not a file: {path:?}"))?;
+
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(
+        ".foro-{}-{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+
+    Ok(path.with_file_name(temp_name))
+}
+
+/// Whether replacing the file by renaming over it would lose something,
+/// i.e. its other hard links.
+#[cfg(unix)]
+fn must_write_in_place(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn must_write_in_place(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Give the temp file the permissions (and, if allowed, the owner) of the original.
+/// Returns false if the owner can't be kept.
+fn copy_metadata(temp_path: &Path, metadata: &fs::Metadata) -> Result<bool> {
+    fs::set_permissions(temp_path, metadata.permissions())?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_metadata = fs::metadata(temp_path)?;
+        if (temp_metadata.uid(), temp_metadata.gid()) != (metadata.uid(), metadata.gid())
+            && std::os::unix::fs::chown(temp_path, Some(metadata.uid()), Some(metadata.gid()))
+                .is_err()
+        {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Write to a temp file next to `path` and rename it over `path`.
+/// Returns false (leaving `path` untouched) if the file can't be replaced without
+/// changing its owner.
+fn replace_atomically(path: &Path, contents: &[u8], metadata: &fs::Metadata) -> Result<bool> {
+    let temp_path = temp_path_for(path)?;
+
+    let res = (|| {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+
+        if !copy_metadata(&temp_path, metadata)? {
+            return Ok(false);
+        }
+
+        fs::rename(&temp_path, path)?;
+        Ok(true)
+    })();
+
+    if !matches!(res, Ok(true)) {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    res
+}
+
+/// Replace the content of the file at `path`, which was read as `original`.
+///
+/// The content is written to a temp file in the same directory, which is renamed
+/// over the file, so that a crash never leaves a truncated file behind. Symlinks
+/// are written through to their target, and the permissions are kept. Files that
+/// can't be replaced without losing hard links or their owner are written in place.
+///
+/// Fails without writing if the file no longer has the `original` content,
+/// e.g. because it was edited while it was being formatted.
+pub fn write_formatted(path: &Path, original: &[u8], formatted: &[u8]) -> Result<()> {
+    let path = fs::canonicalize(path).with_context(|| format!("Failed to resolve {path:?}"))?;
+
+    if fs::read(&path)? != original {
+        bail!("File changed on disk while it was being formatted, not overwriting it");
+    }
+
+    let metadata = fs::metadata(&path)?;
+
+    if !must_write_in_place(&metadata) && replace_atomically(&path, formatted, &metadata)? {
+        return Ok(());
+    }
+
+    debug!("Writing in place: {:?}", path);
+    fs::write(&path, formatted)?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_formatted() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("a.txt");
+        fs::write(&path, "a")?;
+
+        write_formatted(&path, b"a", b"b")?;
+
+        assert_eq!(fs::read_to_string(&path)?, "b");
+        // No temp file is left behind
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_formatted_changed_on_disk() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("a.txt");
+        fs::write(&path, "edited")?;
+
+        assert!(write_formatted(&path, b"a", b"b").is_err());
+        assert_eq!(fs::read_to_string(&path)?, "edited");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_formatted_keeps_permissions_and_symlinks() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new()?;
+        let target = temp_dir.path().join("a.sh");
+        let link = temp_dir.path().join("link.sh");
+        fs::write(&target, "a")?;
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o751))?;
+        std::os::unix::fs::symlink(&target, &link)?;
+
+        write_formatted(&link, b"a", b"b")?;
+
+        assert!(fs::symlink_metadata(&link)?.file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target)?, "b");
+        assert_eq!(fs::metadata(&target)?.permissions().mode() & 0o777, 0o751);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_formatted_keeps_hard_links() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("a.txt");
+        let hard_link = temp_dir.path().join("b.txt");
+        fs::write(&path, "a")?;
+        fs::hard_link(&path, &hard_link)?;
+
+        write_formatted(&path, b"a", b"b")?;
+
+        assert_eq!(fs::read_to_string(&hard_link)?, "b");
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A git repository, driven through the `git` command.
+pub struct GitRepo {
+    /// The directory git commands are run in, which pathspecs are relative to
+    current_dir: PathBuf,
+    /// The top-level directory of the work tree
+    root: PathBuf,
+}
+
+/// Index modes of regular files, the only entries whose content is formatted.
+const REGULAR_FILE_MODES: [&str; 2] = ["100644", "100755"];
+
+/// A file in the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StagedFile {
+    pub mode: String,
+    pub path: PathBuf,
+}
+
+fn run_git(dir: &Path, args: &[&str], stdin: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut child = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run git. Is it installed?")?;
+
+    if let Some(input) = stdin {
+        child.stdin.take().unwrap().write_all(input)?;
+    }
+
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        bail!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(output.stdout)
+}
+
+/// Split the output of a `-z` git command.
+fn split_nul(output: &[u8]) -> impl Iterator<Item = String> + '_ {
+    output
+        .split(|&b| b == 0)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| String::from_utf8_lossy(entry).into_owned())
+}
+
+impl GitRepo {
+    pub fn discover(current_dir: &Path) -> Result<Self> {
+        let root = run_git(current_dir, &["rev-parse", "--show-toplevel"], None)
+            .context("Not in a git repository")?;
+        let root = String::from_utf8(root)?.trim_end().to_string();
+
+        Ok(Self {
+            current_dir: current_dir.to_path_buf(),
+            root: PathBuf::from(root),
+        })
+    }
+
+    /// The directory of the hooks, respecting `core.hooksPath`.
+    pub fn hooks_dir(&self) -> Result<PathBuf> {
+        let dir = self.git(&["rev-parse", "--git-path", "hooks"])?;
+        let dir = String::from_utf8(dir)?.trim_end().to_string();
+
+        Ok(self.current_dir.join(dir))
+    }
+
+    fn git(&self, args: &[&str]) -> Result<Vec<u8>> {
+        run_git(&self.current_dir, args, None)
+    }
+
+    /// Run git with `--` and the pathspecs appended, and return the listed paths
+    /// (which must be relative to the root) as absolute paths.
+    fn list(&self, args: &[&str], pathspecs: &[PathBuf]) -> Result<BTreeSet<PathBuf>> {
+        let mut args = args.to_vec();
+        args.push("--");
+        args.extend(pathspecs.iter().filter_map(|p| p.to_str()));
+
+        Ok(split_nul(&self.git(&args)?)
+            .map(|path| self.root.join(path))
+            .collect())
+    }
+
+    /// Added, copied, modified or renamed files in the index.
+    pub fn staged_paths(&self, pathspecs: &[PathBuf]) -> Result<BTreeSet<PathBuf>> {
+        self.list(
+            &[
+                "diff",
+                "--cached",
+                "--name-only",
+                "-z",
+                "--diff-filter=ACMR",
+            ],
+            pathspecs,
+        )
+    }
+
+    /// Files that differ from `rev` in the work tree, and untracked files.
+    pub fn changed_since(&self, rev: &str, pathspecs: &[PathBuf]) -> Result<BTreeSet<PathBuf>> {
+        let mut paths = self.list(
+            &["diff", "--name-only", "-z", "--diff-filter=ACMR", rev],
+            pathspecs,
+        )?;
+        paths.extend(self.untracked(pathspecs)?);
+        Ok(existing_files(paths))
+    }
+
+    /// Files with staged or unstaged changes, and untracked files.
+    pub fn changed(&self, pathspecs: &[PathBuf]) -> Result<BTreeSet<PathBuf>> {
+        let mut paths = self.staged_paths(pathspecs)?;
+        paths.extend(self.list(&["ls-files", "-z", "--full-name", "--modified"], pathspecs)?);
+        paths.extend(self.untracked(pathspecs)?);
+        Ok(existing_files(paths))
+    }
+
+    fn untracked(&self, pathspecs: &[PathBuf]) -> Result<BTreeSet<PathBuf>> {
+        self.list(
+            &[
+                "ls-files",
+                "-z",
+                "--full-name",
+                "--others",
+                "--exclude-standard",
+            ],
+            pathspecs,
+        )
+    }
+
+    /// Path relative to the root, with `/` separators as git expects.
+    fn git_path(&self, path: &Path) -> Result<String> {
+        let relative = path
+            .strip_prefix(&self.root)
+            .with_context(|| format!("{path:?} is outside of the repository"))?;
+
+        Ok(relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"))
+    }
+
+    /// The mode and content of a file in the index, or `None` if it isn't a regular file
+    /// (e.g. a symlink or a submodule).
+    pub fn read_staged(&self, path: &Path) -> Result<Option<(StagedFile, Vec<u8>)>> {
+        let git_path = self.git_path(path)?;
+
+        let entry = run_git(&self.root, &["ls-files", "-s", "-z", "--", &git_path], None)?;
+        let entry = split_nul(&entry)
+            .next()
+            .ok_or_else(|| anyhow!("{git_path} is not in the index"))?;
+        let mode = entry
+            .split(' ')
+            .next()
+            .ok_or_else(|| anyhow!("Unexpected `git ls-files` output: {entry}"))?;
+
+        if !REGULAR_FILE_MODES.contains(&mode) {
+            return Ok(None);
+        }
+
+        let content = run_git(
+            &self.root,
+            &["cat-file", "blob", &format!(":{git_path}")],
+            None,
+        )?;
+
+        Ok(Some((
+            StagedFile {
+                mode: mode.to_string(),
+                path: path.to_path_buf(),
+            },
+            content,
+        )))
+    }
+
+    /// Replace the content of a file in the index, leaving the work tree alone.
+    pub fn stage(&self, file: &StagedFile, content: &[u8]) -> Result<()> {
+        let git_path = self.git_path(&file.path)?;
+
+        let hash = run_git(
+            &self.root,
+            &["hash-object", "-w", "--no-filters", "--stdin"],
+            Some(content),
+        )?;
+        let hash = String::from_utf8(hash)?.trim_end().to_string();
+
+        run_git(
+            &self.root,
+            &[
+                "update-index",
+                "--cacheinfo",
+                &format!("{},{},{}", file.mode, hash, git_path),
+            ],
+            None,
+        )?;
+
+        Ok(())
+    }
+}
+
+fn existing_files(paths: BTreeSet<PathBuf>) -> BTreeSet<PathBuf> {
+    paths.into_iter().filter(|path| path.is_file()).collect()
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn init_repo() -> Result<(tempfile::TempDir, GitRepo)> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = fs::canonicalize(temp_dir.path())?;
+
+        run_git(&dir, &["init", "-q"], None)?;
+        run_git(&dir, &["config", "user.email", "foro@example.com"], None)?;
+        run_git(&dir, &["config", "user.name", "foro"], None)?;
+
+        let repo = GitRepo::discover(&dir)?;
+        Ok((temp_dir, repo))
+    }
+
+    #[test]
+    fn test_changed_and_staged_paths() -> Result<()> {
+        let (_temp_dir, repo) = init_repo()?;
+        let root = repo.root.clone();
+
+        fs::create_dir(root.join("src"))?;
+        fs::write(root.join("committed.txt"), "a\n")?;
+        fs::write(root.join("src/modified.txt"), "a\n")?;
+        repo.git(&["add", "."])?;
+        repo.git(&["commit", "-q", "-m", "init"])?;
+
+        fs::write(root.join("src/modified.txt"), "b\n")?;
+        fs::write(root.join("staged.txt"), "a\n")?;
+        fs::write(root.join("untracked.txt"), "a\n")?;
+        repo.git(&["add", "staged.txt"])?;
+
+        assert_eq!(
+            repo.staged_paths(&[])?,
+            BTreeSet::from([root.join("staged.txt")])
+        );
+        assert_eq!(
+            repo.changed(&[])?,
+            BTreeSet::from([
+                root.join("src/modified.txt"),
+                root.join("staged.txt"),
+                root.join("untracked.txt"),
+            ])
+        );
+        assert_eq!(
+            repo.changed(&[PathBuf::from("src")])?,
+            BTreeSet::from([root.join("src/modified.txt")])
+        );
+        assert_eq!(repo.changed_since("HEAD", &[])?, repo.changed(&[])?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_and_stage() -> Result<()> {
+        let (_temp_dir, repo) = init_repo()?;
+        let path = repo.root.join("a.txt");
+
+        fs::write(&path, "staged\n")?;
+        repo.git(&["add", "a.txt"])?;
+        fs::write(&path, "unstaged\n")?;
+
+        let (file, content) = repo.read_staged(&path)?.unwrap();
+        assert_eq!(file.mode, "100644");
+        assert_eq!(content, b"staged\n");
+
+        repo.stage(&file, b"restaged\n")?;
+
+        assert_eq!(repo.read_staged(&path)?.unwrap().1, b"restaged\n");
+        assert_eq!(fs::read(&path)?, b"unstaged\n");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_staged_skips_symlinks() -> Result<()> {
+        let (_temp_dir, repo) = init_repo()?;
+        let path = repo.root.join("link.txt");
+
+        fs::write(repo.root.join("a.txt"), "a\n")?;
+        std::os::unix::fs::symlink("a.txt", &path)?;
+        repo.git(&["add", "link.txt"])?;
+
+        assert_eq!(repo.read_staged(&path)?, None);
+
+        Ok(())
+    }
+}
@@ -1,13 +1,15 @@
-use crate::config::{Command, CommandWithControlFlow};
+use crate::config::{Command, CommandWithControlFlow, Rule};
 use crate::debug_long;
+use crate::diff::apply_changes_in_range;
+use crate::fs_utils::write_formatted;
 use crate::handle_plugin::cache::run_multi_cached;
+use crate::target_range::{parse_target_range_json, target_range_json};
 use anyhow::{anyhow, Context, Result};
 use dll_pack::load::{load, Library, NativeLibrary, WasmLibrary};
 use foro_plugin_utils::data_json_utils::{merge, JsonGetter};
 use log::{debug, trace};
 use minijinja;
 use serde_json::{json, to_value, Value};
-use std::fs;
 use std::io::{Read, Write};
 use std::path::Path;
 use url::Url;
@@ -320,23 +322,122 @@ fn run_flow(
     }
 }
 
+/// Restrict the result of a rule to the `target-range`, if one is given.
+///
+/// Unless the plugin reports `range-formatted`, it formatted the whole content,
+/// and only the changes touching the range are kept. The range is then moved onto
+/// the result, so that it stays valid for the next rule.
+fn apply_target_range(cur_json: &mut Value) -> Result<()> {
+    let Some(range) = cur_json
+        .get("target-range")
+        .and_then(parse_target_range_json)
+    else {
+        return Ok(());
+    };
+    let Some(formatted) = String::get_value_opt(cur_json, ["formatted-content"]) else {
+        return Ok(());
+    };
+    let original = String::get_value(cur_json, ["target-content"])?;
+
+    let range_formatted = cur_json
+        .as_object_mut()
+        .unwrap()
+        .remove("range-formatted")
+        .is_some_and(|value| value == json!(true));
+
+    let (formatted, range) = if range_formatted {
+        let end = (range.end + formatted.len()).saturating_sub(original.len());
+        let range = range.start.min(formatted.len())..end.clamp(range.start, formatted.len());
+        (formatted, range)
+    } else {
+        apply_changes_in_range(&original, &formatted, range)
+    };
+
+    cur_json["target-range"] = target_range_json(&formatted, &range);
+    cur_json["formatted-content"] = json!(formatted);
+
+    Ok(())
+}
+
+fn run_rule(rule: &Rule, cur_json: Value, cache_path: &Path, use_cache: bool) -> Result<Value> {
+    let mut res = run_flow(&rule.cmd, cur_json, cache_path, use_cache)?;
+    apply_target_range(&mut res)?;
+
+    Ok(res)
+}
+
+/// Run the commands of the matched rules in order.
+///
+/// The data-json flows between the rules like in `Sequential`, and the `formatted-content`
+/// of a rule becomes the `target-content` of the next one. Rules that ignore the file are
+/// skipped, and an error stops the chain.
+fn run_rules(
+    rules: &[Rule],
+    mut cur_json: Value,
+    cache_path: &Path,
+    use_cache: bool,
+) -> Result<Value> {
+    if let [rule] = rules {
+        return run_rule(rule, cur_json, cache_path, use_cache);
+    }
+
+    let mut last_ignored = None;
+    let mut any_ran = false;
+
+    for rule in rules {
+        let res = run_rule(rule, cur_json.clone(), cache_path, use_cache)?;
+
+        match String::get_value_opt(&res, ["format-status"]).as_deref() {
+            Some("error") => return Ok(res),
+            Some("ignored") => {
+                last_ignored = Some(res);
+                continue;
+            }
+            _ => {}
+        }
+
+        any_ran = true;
+        cur_json = res;
+
+        if let Some(formatted) = String::get_value_opt(&cur_json, ["formatted-content"]) {
+            cur_json["target-content"] = json!(formatted);
+        }
+    }
+
+    match last_ignored {
+        Some(ignored) if !any_ran => Ok(ignored),
+        _ => Ok(cur_json),
+    }
+}
+
+/// Run the commands of the matched rules and write the formatted content back to the target.
+///
+/// If `write` is false, the target is left untouched and the caller is expected to
+/// inspect `formatted-content` in the returned data-json (e.g. for check mode).
 pub fn run(
-    command: &CommandWithControlFlow<Command>,
+    rules: &[Rule],
     cur_json: Value,
     cache_path: &Path,
     use_cache: bool,
+    write: bool,
 ) -> Result<Value> {
-    debug!("run command: {:?}", command);
+    debug!("run rules: {:?}", rules);
     debug_long!("data-json: {:?}", &cur_json);
 
     let target_path = String::get_value(&cur_json, ["os-target"])?;
     let original_content = String::get_value(&cur_json, ["target-content"])?;
 
-    let res = run_flow(command, cur_json, cache_path, use_cache)?;
+    let res = run_rules(rules, cur_json, cache_path, use_cache)?;
 
-    if let Some(formatted) = String::get_value_opt(&res, ["formatted-content"]) {
-        if formatted != original_content {
-            fs::write(target_path, formatted)?;
+    if write {
+        if let Some(formatted) = String::get_value_opt(&res, ["formatted-content"]) {
+            if formatted != original_content {
+                write_formatted(
+                    Path::new(&target_path),
+                    original_content.as_bytes(),
+                    formatted.as_bytes(),
+                )?;
+            }
         }
     }
 
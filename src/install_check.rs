@@ -1,15 +1,12 @@
-use crate::config::load_str;
-use anyhow::{bail, Context, Result};
+use crate::config::Config;
+use anyhow::{bail, Result};
 use std::collections::BTreeSet;
 use std::fs;
 use std::path::{Path, PathBuf};
 use xxhash_rust::xxh3::xxh3_128;
 
-pub fn config_hash(config_bytes: &[u8]) -> Result<String> {
-    let config_text =
-        std::str::from_utf8(config_bytes).context("Failed to read config as UTF-8")?;
-    let config = load_str(config_text)?;
-
+/// Hash of the plugins required by a fully resolved config (i.e. after `extends`).
+pub fn config_hash(config: &Config) -> String {
     let urls = config
         .all_plugin_urls()
         .into_iter()
@@ -18,16 +15,20 @@ pub fn config_hash(config_bytes: &[u8]) -> Result<String> {
 
     let normalized = urls.into_iter().collect::<Vec<_>>().join("\0");
 
-    Ok(format!("{:032x}", xxh3_128(normalized.as_bytes())))
+    format!("{:032x}", xxh3_128(normalized.as_bytes()))
 }
 
 fn marker_path(cache_dir: &Path, hash: &str) -> PathBuf {
     cache_dir.join("ready").join(hash)
 }
 
-pub fn check_ready(config_bytes: &[u8], cache_dir: &Path) -> Result<()> {
-    let hash = config_hash(config_bytes)?;
-    let marker = marker_path(cache_dir, &hash);
+/// The file that marks the plugins of the config as installed in the cache directory.
+pub fn ready_marker(config: &Config, cache_dir: &Path) -> PathBuf {
+    marker_path(cache_dir, &config_hash(config))
+}
+
+pub fn check_ready(config: &Config, cache_dir: &Path) -> Result<()> {
+    let marker = ready_marker(config, cache_dir);
     if !marker.exists() {
         bail!(
             "plugins not downloaded for current config.\n\
@@ -37,8 +38,8 @@ pub fn check_ready(config_bytes: &[u8], cache_dir: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn mark_ready(config_bytes: &[u8], cache_dir: &Path) -> Result<()> {
-    let hash = config_hash(config_bytes)?;
+pub fn mark_ready(config: &Config, cache_dir: &Path) -> Result<()> {
+    let hash = config_hash(config);
     let ready_dir = cache_dir.join("ready");
     fs::create_dir_all(&ready_dir)?;
     fs::write(ready_dir.join(hash), "")?;
@@ -0,0 +1,297 @@
+use crate::daemon::client::{ensure_daemon_running, DaemonConnection};
+use crate::daemon::interface::{
+    DaemonCommands, DaemonExecutionOptions, DaemonFormatArgs, DaemonFormatResponse, DaemonResponse,
+    DaemonSocketPath, TargetRange, TextEdit, TextPosition,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationTrait,
+};
+use lsp_types::request::{Formatting, RangeFormatting, Request as RequestTrait, WillSaveWaitUntil};
+use lsp_types::{
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentFormattingParams, DocumentRangeFormattingParams, OneOf, Position, Range,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
+    Uri, WillSaveTextDocumentParams,
+};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// A language server that formats documents with the daemon.
+///
+/// The content of open documents is kept in sync (with full updates), so that unsaved
+/// changes are formatted. Other documents are read from disk.
+pub struct LspServer {
+    socket: DaemonSocketPath,
+    execution_options: DaemonExecutionOptions,
+    /// Kept open across requests, and reconnected if the daemon goes away
+    daemon: Option<DaemonConnection>,
+    documents: HashMap<Uri, String>,
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                will_save_wait_until: Some(true),
+                ..Default::default()
+            },
+        )),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+/// Run a language server on stdin/stdout until the client shuts it down.
+pub fn run_lsp_server(
+    socket: DaemonSocketPath,
+    execution_options: DaemonExecutionOptions,
+) -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    info!("LSP server initialized");
+
+    let mut server = LspServer {
+        socket,
+        execution_options,
+        daemon: None,
+        documents: HashMap::new(),
+    };
+    server.main_loop(&connection)?;
+
+    drop(connection);
+    io_threads.join()?;
+
+    info!("LSP server stopped");
+
+    Ok(())
+}
+
+fn parse_params<P: DeserializeOwned>(params: serde_json::Value) -> Result<P> {
+    serde_json::from_value(params).context("Invalid params")
+}
+
+fn uri_to_path(uri: &Uri) -> Result<PathBuf> {
+    url::Url::parse(uri.as_str())
+        .ok()
+        .and_then(|url| url.to_file_path().ok())
+        .ok_or_else(|| anyhow!("Not a file URI: {}", uri.as_str()))
+}
+
+/// The byte offset of an LSP position (in UTF-16 code units) in `content`.
+/// Positions past the end of a line or of the content are clamped.
+fn position_to_offset(content: &str, position: Position) -> usize {
+    let line_start = match position.line {
+        0 => 0,
+        line => match content.match_indices('\n').nth(line as usize - 1) {
+            Some((i, _)) => i + 1,
+            None => return content.len(),
+        },
+    };
+    let line = &content[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+
+    let mut character = 0;
+    for (i, c) in line.char_indices() {
+        if character >= position.character as usize {
+            return line_start + i;
+        }
+        character += c.len_utf16();
+    }
+
+    line_start + line.len()
+}
+
+fn to_lsp_position(position: TextPosition) -> Position {
+    Position::new(position.line as u32, position.character as u32)
+}
+
+fn to_lsp_edit(edit: TextEdit) -> lsp_types::TextEdit {
+    lsp_types::TextEdit::new(
+        Range::new(
+            to_lsp_position(edit.start_position),
+            to_lsp_position(edit.end_position),
+        ),
+        edit.new_text,
+    )
+}
+
+impl LspServer {
+    fn main_loop(&mut self, connection: &Connection) -> Result<()> {
+        for message in &connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+
+                    let response = self.handle_request(request);
+                    connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => {
+                    if let Err(err) = self.handle_notification(notification) {
+                        debug!("Failed to handle notification: {err:#}");
+                    }
+                }
+                Message::Response(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_request(&mut self, request: Request) -> Response {
+        let result = match request.method.as_str() {
+            Formatting::METHOD => parse_params::<DocumentFormattingParams>(request.params)
+                .and_then(|params| self.format(&params.text_document.uri, None)),
+            RangeFormatting::METHOD => {
+                parse_params::<DocumentRangeFormattingParams>(request.params)
+                    .and_then(|params| self.format(&params.text_document.uri, Some(params.range)))
+            }
+            WillSaveWaitUntil::METHOD => parse_params::<WillSaveTextDocumentParams>(request.params)
+                .and_then(|params| self.format(&params.text_document.uri, None)),
+            method => {
+                return Response::new_err(
+                    request.id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unsupported method: {method}"),
+                )
+            }
+        };
+
+        match result {
+            Ok(edits) => Response::new_ok(request.id, edits),
+            Err(err) => Response::new_err(
+                request.id,
+                ErrorCode::RequestFailed as i32,
+                format!("{err:#}"),
+            ),
+        }
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = parse_params(notification.params)?;
+                self.documents
+                    .insert(params.text_document.uri, params.text_document.text);
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = parse_params(notification.params)?;
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(params.text_document.uri, change.text);
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = parse_params(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Send a command to the daemon over the kept connection. If that fails, the daemon
+    /// is started if needed, and the command is sent again over a new connection.
+    fn daemon_request(&mut self, command: DaemonCommands) -> Result<DaemonResponse> {
+        if let Some(daemon) = &mut self.daemon {
+            match daemon.request(command.clone(), self.execution_options.clone()) {
+                Ok(response) => return Ok(response),
+                Err(err) => debug!("Reconnecting to the daemon: {err:#}"),
+            }
+        }
+
+        // The daemon may have been stopped or restarted since the last request
+        ensure_daemon_running(&self.socket, &self.execution_options)?;
+
+        let daemon = self.daemon.insert(DaemonConnection::connect(&self.socket)?);
+        daemon.request(command, self.execution_options.clone())
+    }
+
+    /// Format a document (or a range of it) with the daemon.
+    /// Returns `None` if the document is ignored by foro.
+    fn format(
+        &mut self,
+        uri: &Uri,
+        range: Option<Range>,
+    ) -> Result<Option<Vec<lsp_types::TextEdit>>> {
+        let path = uri_to_path(uri)?;
+        let content = match self.documents.get(uri) {
+            Some(content) => content.clone(),
+            None => {
+                fs::read_to_string(&path).with_context(|| format!("Failed to read {path:?}"))?
+            }
+        };
+
+        let range = range.map(|range| TargetRange::Bytes {
+            start: position_to_offset(&content, range.start),
+            end: position_to_offset(&content, range.end),
+        });
+
+        let response = self.daemon_request(DaemonCommands::Format(DaemonFormatArgs {
+            path,
+            content,
+            check: false,
+            diff: false,
+            return_content: false,
+            return_edits: true,
+            range,
+        }))?;
+
+        match response {
+            DaemonResponse::Format(DaemonFormatResponse::Edits(edits)) => {
+                Ok(Some(edits.into_iter().map(to_lsp_edit).collect()))
+            }
+            DaemonResponse::Format(DaemonFormatResponse::Ignored(reason)) => {
+                debug!("Ignored {}: {reason}", uri.as_str());
+                Ok(None)
+            }
+            DaemonResponse::Format(DaemonFormatResponse::Error(err)) => Err(anyhow!(err)),
+            other => bail!("Unexpected response from the daemon: {other:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_to_offset() {
+        let content = "ab\n\"😀\"x\nc";
+
+        assert_eq!(position_to_offset(content, Position::new(0, 1)), 1);
+        assert_eq!(position_to_offset(content, Position::new(1, 0)), 3);
+        // The emoji is 2 UTF-16 code units, but 4 bytes
+        assert_eq!(position_to_offset(content, Position::new(1, 3)), 8);
+        // Clamped to the end of the line, and of the content
+        assert_eq!(position_to_offset(content, Position::new(0, 10)), 2);
+        assert_eq!(
+            position_to_offset(content, Position::new(5, 0)),
+            content.len()
+        );
+    }
+
+    #[test]
+    fn test_uri_to_path() -> Result<()> {
+        #[cfg(not(windows))]
+        assert_eq!(
+            uri_to_path(&"file:///tmp/a%20b.rs".parse()?)?,
+            PathBuf::from("/tmp/a b.rs")
+        );
+        assert!(uri_to_path(&"untitled:Untitled-1".parse()?).is_err());
+
+        Ok(())
+    }
+}
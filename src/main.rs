@@ -9,11 +9,18 @@ mod bulk_format;
 mod cli;
 mod config;
 mod daemon;
+mod diff;
+mod fs_utils;
+mod git;
 mod handle_plugin;
 mod install_check;
 mod log;
+mod lsp;
 mod path_utils;
 mod process_utils;
+mod report;
+mod result_cache;
+mod target_range;
 
 use anyhow::Result;
 
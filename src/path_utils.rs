@@ -1,5 +1,7 @@
 use anyhow::{Context, Result};
-use std::path::Path;
+use std::io;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 
 // Suppress unused warnings in unix
 #[allow(unused)]
@@ -27,9 +29,26 @@ fn convert_windows_path(path_str: &str) -> Result<String> {
     Ok(path_str.to_string())
 }
 
+/// Like [Path::canonicalize], but also accepts a path whose last component doesn't exist.
+///
+/// When formatting stdin, the target path is only a hint for rule matching and
+/// plugin config discovery, so the file itself may not exist on disk.
+pub fn canonicalize_allow_missing(path: &Path) -> io::Result<PathBuf> {
+    match path.canonicalize() {
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+                return Err(err);
+            };
+
+            Ok(parent.canonicalize()?.join(file_name))
+        }
+        res => res,
+    }
+}
+
 #[cfg(not(windows))]
 pub fn normalize_path(path: &Path) -> Result<String> {
-    path.canonicalize()?
+    canonicalize_allow_missing(path)?
         .to_str()
         .map(|s| s.to_string())
         .ok_or_else(|| anyhow::anyhow!("Failed to convert path to string"))
@@ -39,7 +58,7 @@ pub fn normalize_path(path: &Path) -> Result<String> {
 pub fn normalize_path(path: &Path) -> Result<String> {
     use anyhow::Context;
 
-    let abs = path.canonicalize()?;
+    let abs = canonicalize_allow_missing(path)?;
 
     // This is like be `\\?\C:\\Users\...`.
     let path_str = abs.to_str().context("Failed to convert path to string")?;
@@ -49,7 +68,7 @@ pub fn normalize_path(path: &Path) -> Result<String> {
 
 #[cfg(not(windows))]
 pub fn to_wasm_path(path: &Path) -> Result<String> {
-    path.canonicalize()?
+    canonicalize_allow_missing(path)?
         .to_str()
         .map(|s| s.to_string())
         .ok_or_else(|| anyhow::anyhow!("Failed to convert path to string"))
@@ -59,7 +78,7 @@ pub fn to_wasm_path(path: &Path) -> Result<String> {
 pub fn to_wasm_path(path: &Path) -> Result<String> {
     use anyhow::Context;
 
-    let abs = path.canonicalize()?;
+    let abs = canonicalize_allow_missing(path)?;
 
     // This is like be `\\?\C:\\Users\...`.
     let path_str = abs.to_str().context("Failed to convert path to string")?;
@@ -88,6 +107,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_canonicalize_allow_missing() {
+        let pwd = std::env::current_dir().unwrap().canonicalize().unwrap();
+
+        assert_eq!(
+            canonicalize_allow_missing(&pwd.join("does-not-exist.rs")).unwrap(),
+            pwd.join("does-not-exist.rs")
+        );
+        assert!(canonicalize_allow_missing(&pwd.join("no-such-dir/main.rs")).is_err());
+    }
+
     #[cfg_attr(not(windows), ignore)]
     #[test]
     fn test_normalize_path_windows() {
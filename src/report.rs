@@ -0,0 +1,346 @@
+use crate::daemon::client::relative_to_cwd;
+use crate::daemon::interface::{BulkFormatSummary, FileResult, FileStatus};
+use clap::ValueEnum;
+use serde_json::json;
+use std::path::Path;
+
+const UNFORMATTED_RULE: &str = "unformatted";
+const ERROR_RULE: &str = "format-error";
+const UNFORMATTED_MESSAGE: &str = "File is not formatted";
+
+/// Machine-readable reports of a bulk format, so that CI can annotate the failing files.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// The counts and the outcome of each file
+    Json,
+    /// SARIF 2.1.0, e.g. for GitHub code scanning
+    Sarif,
+    /// Checkstyle XML, with the files that failed
+    Checkstyle,
+    /// JUnit XML, with a test case per file
+    Junit,
+}
+
+/// A file that fails the run: an error, or an unformatted file in check mode.
+struct Finding<'a> {
+    path: String,
+    rule: &'static str,
+    message: &'a str,
+}
+
+fn display_path(path: &Path) -> String {
+    relative_to_cwd(path).display().to_string()
+}
+
+fn finding(file: &FileResult, check: bool) -> Option<Finding<'_>> {
+    let (rule, message) = match file.status {
+        FileStatus::Error => (
+            ERROR_RULE,
+            file.message.as_deref().unwrap_or("Failed to format"),
+        ),
+        // Without --check, changed files have been fixed, so they aren't findings
+        FileStatus::Changed if check => (UNFORMATTED_RULE, UNFORMATTED_MESSAGE),
+        _ => return None,
+    };
+
+    Some(Finding {
+        path: display_path(&file.path),
+        rule,
+        message,
+    })
+}
+
+fn findings(summary: &BulkFormatSummary, check: bool) -> Vec<Finding<'_>> {
+    summary
+        .files
+        .iter()
+        .filter_map(|file| finding(file, check))
+        .collect()
+}
+
+/// Escape text for XML attributes. Control characters other than tab, newline and
+/// carriage return are invalid in XML 1.0 even as references, so they're dropped
+/// (e.g. the ESC of color codes in tool output).
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\r' => escaped.push_str("&#13;"),
+            '\t' => escaped.push_str("&#9;"),
+            c if c.is_ascii_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn render_json(summary: &BulkFormatSummary) -> String {
+    let files: Vec<_> = summary
+        .files
+        .iter()
+        .map(|file| {
+            let mut value = json!({
+                "path": display_path(&file.path),
+                "status": file.status,
+            });
+            if let Some(message) = &file.message {
+                value["message"] = json!(message);
+            }
+            value
+        })
+        .collect();
+
+    let report = json!({
+        "total_count": summary.total_count,
+        "changed_count": summary.changed_count,
+        "unchanged_count": summary.unchanged_count,
+        "ignored_count": summary.ignored_count,
+        "error_count": summary.error_count,
+        "cancelled": summary.cancelled,
+        "files": files,
+    });
+
+    format!("{}\n", serde_json::to_string_pretty(&report).unwrap())
+}
+
+fn render_sarif(summary: &BulkFormatSummary, check: bool) -> String {
+    let results: Vec<_> = findings(summary, check)
+        .into_iter()
+        .map(|finding| {
+            json!({
+                "ruleId": finding.rule,
+                "level": "error",
+                "message": { "text": finding.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": finding.path.replace('\\', "/") }
+                    }
+                }],
+            })
+        })
+        .collect();
+
+    let report = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "foro",
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": [
+                        {
+                            "id": UNFORMATTED_RULE,
+                            "shortDescription": { "text": UNFORMATTED_MESSAGE },
+                        },
+                        {
+                            "id": ERROR_RULE,
+                            "shortDescription": { "text": "File could not be formatted" },
+                        },
+                    ],
+                }
+            },
+            "results": results,
+        }],
+    });
+
+    format!("{}\n", serde_json::to_string_pretty(&report).unwrap())
+}
+
+fn render_checkstyle(summary: &BulkFormatSummary, check: bool) -> String {
+    let mut xml =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<checkstyle version=\"4.3\">\n");
+
+    for finding in findings(summary, check) {
+        xml.push_str(&format!(
+            "  <file name=\"{}\">\n    <error line=\"1\" severity=\"error\" message=\"{}\" source=\"foro.{}\"/>\n  </file>\n",
+            xml_escape(&finding.path),
+            xml_escape(finding.message),
+            finding.rule
+        ));
+    }
+
+    xml.push_str("</checkstyle>\n");
+    xml
+}
+
+fn render_junit(summary: &BulkFormatSummary, check: bool) -> String {
+    let mut test_cases = String::new();
+    let (mut failures, mut errors, mut skipped) = (0, 0, 0);
+
+    for file in &summary.files {
+        let name = xml_escape(&display_path(&file.path));
+
+        let body = match finding(file, check) {
+            Some(finding) if finding.rule == ERROR_RULE => {
+                errors += 1;
+                format!("<error message=\"{}\"/>", xml_escape(finding.message))
+            }
+            Some(finding) => {
+                failures += 1;
+                format!("<failure message=\"{}\"/>", xml_escape(finding.message))
+            }
+            None if file.status == FileStatus::Ignored => {
+                skipped += 1;
+                "<skipped/>".to_string()
+            }
+            None => {
+                test_cases.push_str(&format!(
+                    "    <testcase name=\"{name}\" classname=\"foro\"/>\n"
+                ));
+                continue;
+            }
+        };
+
+        test_cases.push_str(&format!(
+            "    <testcase name=\"{name}\" classname=\"foro\">\n      {body}\n    </testcase>\n"
+        ));
+    }
+
+    let counts = format!(
+        "tests=\"{}\" failures=\"{failures}\" errors=\"{errors}\" skipped=\"{skipped}\"",
+        summary.files.len()
+    );
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"foro\" {counts}>\n  <testsuite name=\"foro\" {counts}>\n{test_cases}  </testsuite>\n</testsuites>\n"
+    )
+}
+
+/// Render the outcome of each file of a bulk format.
+/// With `check`, unformatted files are reported as failures.
+pub fn render_report(format: ReportFormat, summary: &BulkFormatSummary, check: bool) -> String {
+    match format {
+        ReportFormat::Json => render_json(summary),
+        ReportFormat::Sarif => render_sarif(summary, check),
+        ReportFormat::Checkstyle => render_checkstyle(summary, check),
+        ReportFormat::Junit => render_junit(summary, check),
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn summary() -> BulkFormatSummary {
+        let file = |path: &str, status, message: Option<&str>| FileResult {
+            path: PathBuf::from(path),
+            status,
+            message: message.map(str::to_string),
+        };
+
+        BulkFormatSummary {
+            total_count: 4,
+            changed_count: 1,
+            unchanged_count: 1,
+            ignored_count: 1,
+            error_count: 1,
+            diffs: Vec::new(),
+            files: vec![
+                file("a.rs", FileStatus::Changed, None),
+                file("b.rs", FileStatus::Error, Some("expected `<`, found \"x\"")),
+                file("c.txt", FileStatus::Ignored, None),
+                file("d.rs", FileStatus::Unchanged, None),
+            ],
+            cancelled: false,
+        }
+    }
+
+    #[test]
+    fn test_render_json() {
+        let report: serde_json::Value =
+            serde_json::from_str(&render_report(ReportFormat::Json, &summary(), true)).unwrap();
+
+        assert_eq!(report["error_count"], 1);
+        assert_eq!(
+            report["files"][0],
+            json!({"path": "a.rs", "status": "changed"})
+        );
+        assert_eq!(
+            report["files"][1],
+            json!({"path": "b.rs", "status": "error", "message": "expected `<`, found \"x\""})
+        );
+    }
+
+    #[test]
+    fn test_render_sarif() {
+        let report: serde_json::Value =
+            serde_json::from_str(&render_report(ReportFormat::Sarif, &summary(), true)).unwrap();
+        let results = report["runs"][0]["results"].as_array().unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["ruleId"], UNFORMATTED_RULE);
+        assert_eq!(
+            results[1]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+            "b.rs"
+        );
+
+        // Without --check, changed files have been fixed
+        let report: serde_json::Value =
+            serde_json::from_str(&render_report(ReportFormat::Sarif, &summary(), false)).unwrap();
+        assert_eq!(report["runs"][0]["results"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_render_checkstyle() {
+        assert_eq!(
+            render_report(ReportFormat::Checkstyle, &summary(), true),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<checkstyle version="4.3">
+  <file name="a.rs">
+    <error line="1" severity="error" message="File is not formatted" source="foro.unformatted"/>
+  </file>
+  <file name="b.rs">
+    <error line="1" severity="error" message="expected `&lt;`, found &quot;x&quot;" source="foro.format-error"/>
+  </file>
+</checkstyle>
+"#
+        );
+    }
+
+    #[test]
+    fn test_xml_escape_drops_control_characters() {
+        assert_eq!(
+            xml_escape("\x1b[31merror\x1b[0m:\tline\r\n\x07"),
+            "[31merror[0m:&#9;line&#13;&#10;"
+        );
+
+        let mut summary = summary();
+        summary.files[1].message = Some("\x1b[31mexpected `;`\x1b[0m".to_string());
+        let xml = render_report(ReportFormat::Checkstyle, &summary, true);
+        assert!(xml.contains("message=\"[31mexpected `;`[0m\""));
+        assert!(!xml.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_render_junit() {
+        assert_eq!(
+            render_report(ReportFormat::Junit, &summary(), true),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="foro" tests="4" failures="1" errors="1" skipped="1">
+  <testsuite name="foro" tests="4" failures="1" errors="1" skipped="1">
+    <testcase name="a.rs" classname="foro">
+      <failure message="File is not formatted"/>
+    </testcase>
+    <testcase name="b.rs" classname="foro">
+      <error message="expected `&lt;`, found &quot;x&quot;"/>
+    </testcase>
+    <testcase name="c.txt" classname="foro">
+      <skipped/>
+    </testcase>
+    <testcase name="d.rs" classname="foro"/>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+}
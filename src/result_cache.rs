@@ -0,0 +1,510 @@
+use crate::build_info::get_build_id;
+use crate::config::{Command, CommandWithControlFlow, Rule};
+use anyhow::Result;
+use log::{debug, trace};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use url::Url;
+use xxhash_rust::xxh3::{xxh3_128, Xxh3};
+
+/// Entries that haven't been used for this long are removed by [ResultCache::prune].
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How often the entries are pruned, and how often a used entry is marked as used.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The files the known formatters read their settings from, by the name of the plugin
+/// (e.g. `rustfmt` for `foro-rustfmt.dllpack`) or of the `io` executable.
+const FORMATTER_CONFIG_FILES: &[(&str, &[&str])] = &[
+    ("rustfmt", &["rustfmt.toml", ".rustfmt.toml"]),
+    (
+        "biome",
+        &[
+            "biome.json",
+            "biome.jsonc",
+            ".biome.json",
+            ".biome.jsonc",
+            ".editorconfig",
+        ],
+    ),
+    ("ruff", &["ruff.toml", ".ruff.toml", "pyproject.toml"]),
+    ("black", &["pyproject.toml"]),
+    ("clang-format", &[".clang-format", "_clang-format"]),
+    ("tombi", &["tombi.toml", ".tombi.toml", "pyproject.toml"]),
+    ("taplo", &["taplo.toml", ".taplo.toml"]),
+    (
+        "prettier",
+        &[
+            ".prettierrc",
+            ".prettierrc.json",
+            ".prettierrc.json5",
+            ".prettierrc.yaml",
+            ".prettierrc.yml",
+            ".prettierrc.toml",
+            ".prettierrc.js",
+            ".prettierrc.cjs",
+            ".prettierrc.mjs",
+            "prettier.config.js",
+            "prettier.config.cjs",
+            "prettier.config.mjs",
+            "package.json",
+            ".editorconfig",
+        ],
+    ),
+    (
+        "dprint",
+        &[
+            "dprint.json",
+            "dprint.jsonc",
+            ".dprint.json",
+            ".dprint.jsonc",
+        ],
+    ),
+    ("stylua", &["stylua.toml", ".stylua.toml"]),
+    ("shfmt", &[".editorconfig"]),
+    ("gofmt", &[]),
+];
+
+/// A formatter run by a rule.
+enum Formatter<'a> {
+    Plugin(&'a Url),
+    Io(&'a str),
+}
+
+fn collect_formatters<'a>(
+    cmd: &'a CommandWithControlFlow<Command>,
+    formatters: &mut Vec<Formatter<'a>>,
+) {
+    match cmd {
+        CommandWithControlFlow::Command(Command::CommandIO { io }) => {
+            formatters.push(Formatter::Io(io))
+        }
+        CommandWithControlFlow::Command(Command::PluginUrl(url)) => {
+            formatters.push(Formatter::Plugin(url))
+        }
+        CommandWithControlFlow::Sequential(cmds) => {
+            for c in cmds {
+                collect_formatters(c, formatters);
+            }
+        }
+        CommandWithControlFlow::If {
+            run,
+            on_true,
+            on_false,
+            ..
+        } => {
+            collect_formatters(run, formatters);
+            collect_formatters(on_true, formatters);
+            collect_formatters(on_false, formatters);
+        }
+        CommandWithControlFlow::Set { .. } => {}
+    }
+}
+
+/// The name of a plugin, e.g. `rustfmt` for `.../foro-rustfmt.dllpack`.
+fn plugin_name(url: &Url) -> Option<&str> {
+    let file_name = url.path_segments()?.next_back()?;
+    let name = file_name.strip_suffix(".dllpack").unwrap_or(file_name);
+    Some(name.strip_prefix("foro-").unwrap_or(name))
+}
+
+/// The config files of a formatter. Formatters that aren't known may read any of them.
+fn formatter_config_files(name: Option<&str>) -> Vec<&'static str> {
+    let known = name.and_then(|name| {
+        FORMATTER_CONFIG_FILES
+            .iter()
+            .find(|(formatter, _)| *formatter == name)
+    });
+
+    match known {
+        Some((_, files)) => files.to_vec(),
+        None => FORMATTER_CONFIG_FILES
+            .iter()
+            .flat_map(|(_, files)| files.iter().copied())
+            .collect(),
+    }
+}
+
+/// The executable an `io` command runs, as a path or a name to look up in `PATH`.
+#[cfg(not(windows))]
+fn io_executable(io: &str) -> Option<String> {
+    shell_words::split(io).ok()?.into_iter().next()
+}
+
+#[cfg(windows)]
+fn io_executable(io: &str) -> Option<String> {
+    io.split_whitespace().next().map(str::to_string)
+}
+
+/// Hash of the executable's path, size and modification time, so that
+/// updating the tool invalidates the cached results.
+fn executable_fingerprint(exec: &str, dir: &Path) -> u128 {
+    let path = if exec.contains('/') {
+        Some(dir.join(exec))
+    } else {
+        std::env::var_os("PATH").and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|path| path.join(exec))
+                .find(|path| path.is_file())
+        })
+    };
+
+    let mut hasher = Xxh3::new();
+    hasher.update(exec.as_bytes());
+
+    if let Some(path) = path.and_then(|path| fs::canonicalize(path).ok()) {
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        if let Ok(metadata) = fs::metadata(&path) {
+            hasher.update(&metadata.len().to_le_bytes());
+            if let Ok(modified) = metadata.modified() {
+                hasher.update(format!("{modified:?}").as_bytes());
+            }
+        }
+    }
+
+    hasher.digest128()
+}
+
+/// A persistent record of the files known to be formatted, so that they
+/// aren't sent through the plugins again.
+///
+/// Each target has an entry keyed by its path, the matched rules (and so their plugin
+/// URLs), the build id, the config files of the formatters (and the rules' extra `inputs`)
+/// found around it and the executables of the `io` commands, which holds the hash of its
+/// formatted content. A change to any of them misses the entry.
+///
+/// The fingerprints of the inputs and executables are computed once per instance,
+/// so an instance must not outlive a single bulk format.
+pub struct ResultCache {
+    dir: PathBuf,
+    /// Fingerprints of the inputs in a directory and its ancestors, by input names and directory.
+    input_fingerprints: Mutex<HashMap<(Vec<String>, PathBuf), u128>>,
+    executable_fingerprints: Mutex<HashMap<String, u128>>,
+}
+
+impl ResultCache {
+    pub fn new(cache_dir: &Path) -> Self {
+        Self {
+            dir: cache_dir.join("results"),
+            input_fingerprints: Mutex::new(HashMap::new()),
+            executable_fingerprints: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn input_fingerprint(&self, inputs: &[String], dir: &Path) -> u128 {
+        let key = (inputs.to_vec(), dir.to_path_buf());
+        if let Some(fingerprint) = self.input_fingerprints.lock().unwrap().get(&key) {
+            return *fingerprint;
+        }
+
+        let mut hasher = Xxh3::new();
+        if let Some(parent) = dir.parent() {
+            hasher.update(&self.input_fingerprint(inputs, parent).to_le_bytes());
+        }
+
+        for name in inputs {
+            let path = dir.join(name);
+            if let Ok(content) = fs::read(&path) {
+                trace!("cache input: {:?}", path);
+                hasher.update(path.as_os_str().as_encoded_bytes());
+                hasher.update(b"\0");
+                hasher.update(&xxh3_128(&content).to_le_bytes());
+            }
+        }
+
+        let fingerprint = hasher.digest128();
+        self.input_fingerprints
+            .lock()
+            .unwrap()
+            .insert(key, fingerprint);
+        fingerprint
+    }
+
+    fn executable_fingerprint(&self, exec: &str, dir: &Path) -> u128 {
+        // Relative paths depend on the directory of the target
+        if exec.contains('/') {
+            return executable_fingerprint(exec, dir);
+        }
+
+        *self
+            .executable_fingerprints
+            .lock()
+            .unwrap()
+            .entry(exec.to_string())
+            .or_insert_with(|| executable_fingerprint(exec, dir))
+    }
+
+    /// The entry of `target`, or `None` if it has no parent directory.
+    pub fn entry(&self, target: &Path, rules: &[Rule], content: &str) -> Option<ResultCacheEntry> {
+        let dir = target.parent()?;
+
+        let mut formatters = Vec::new();
+        for rule in rules {
+            collect_formatters(&rule.cmd, &mut formatters);
+        }
+
+        let mut inputs = BTreeSet::new();
+        for rule in rules {
+            if let Some(cache) = &rule.cache {
+                inputs.extend(cache.inputs.iter().cloned());
+            }
+        }
+        for formatter in &formatters {
+            let config_files = match formatter {
+                Formatter::Plugin(url) => formatter_config_files(plugin_name(url)),
+                Formatter::Io(io) => formatter_config_files(
+                    io_executable(io)
+                        .as_deref()
+                        .and_then(|exec| Path::new(exec).file_name())
+                        .and_then(|name| name.to_str()),
+                ),
+            };
+            inputs.extend(config_files.into_iter().map(str::to_string));
+        }
+        let inputs: Vec<_> = inputs.into_iter().collect();
+
+        let mut hasher = Xxh3::new();
+        hasher.update(get_build_id().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(target.as_os_str().as_encoded_bytes());
+        hasher.update(b"\0");
+        hasher.update(serde_json::to_string(rules).unwrap().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(&self.input_fingerprint(&inputs, dir).to_le_bytes());
+
+        for formatter in formatters {
+            if let Formatter::Io(io) = formatter {
+                if let Some(exec) = io_executable(io) {
+                    hasher.update(&self.executable_fingerprint(&exec, dir).to_le_bytes());
+                }
+            }
+        }
+
+        let key = format!("{:032x}", hasher.digest128());
+
+        Some(ResultCacheEntry {
+            // The entries are sharded, so that no directory gets too large
+            path: self.dir.join(&key[..2]).join(&key[2..]),
+            content_hash: format!("{:032x}", xxh3_128(content.as_bytes())),
+        })
+    }
+
+    /// Remove the entries that haven't been used for [MAX_AGE], e.g. of moved files.
+    /// Does nothing if the entries were pruned less than [PRUNE_INTERVAL] ago.
+    pub fn prune(&self) -> Result<()> {
+        let marker = self.dir.join(".pruned");
+        if !self.dir.is_dir() || !is_older_than(&marker, PRUNE_INTERVAL) {
+            return Ok(());
+        }
+
+        debug!("Pruning cached results: {:?}", self.dir);
+
+        for shard in fs::read_dir(&self.dir)? {
+            let shard = shard?.path();
+            if !shard.is_dir() {
+                continue;
+            }
+
+            for entry in fs::read_dir(&shard)? {
+                let entry = entry?.path();
+                if is_older_than(&entry, MAX_AGE) {
+                    trace!("Removing cached result: {:?}", entry);
+                    fs::remove_file(&entry)?;
+                }
+            }
+
+            // Only succeeds if the shard is empty
+            let _ = fs::remove_dir(&shard);
+        }
+
+        fs::write(&marker, "")?;
+        Ok(())
+    }
+}
+
+/// Whether the file was last modified longer than `age` ago, or doesn't exist.
+fn is_older_than(path: &Path, age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_or(true, |modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|elapsed| elapsed > age)
+        })
+}
+
+/// The cached result of a target, see [ResultCache].
+pub struct ResultCacheEntry {
+    path: PathBuf,
+    content_hash: String,
+}
+
+impl ResultCacheEntry {
+    /// Whether the content is known to be formatted.
+    pub fn is_formatted(&self) -> bool {
+        if !fs::read_to_string(&self.path).is_ok_and(|hash| hash == self.content_hash) {
+            return false;
+        }
+
+        // Entries in use are rewritten now and then, so that they aren't pruned
+        if is_older_than(&self.path, PRUNE_INTERVAL) {
+            let _ = fs::write(&self.path, &self.content_hash);
+        }
+        true
+    }
+
+    /// Record that the content is formatted.
+    pub fn mark_formatted(&self) -> Result<()> {
+        debug!("Caching formatted result: {:?}", self.path);
+
+        fs::create_dir_all(self.path.parent().unwrap())?;
+        fs::write(&self.path, &self.content_hash)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn rules(json: &str) -> Vec<Rule> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn cached_rules() -> Vec<Rule> {
+        rules(r#"[{"on": ".txt", "cmd": {"io": "cat"}, "cache": {"inputs": ["fmt.toml"]}}]"#)
+    }
+
+    #[test]
+    fn test_result_cache() {
+        let cache_dir = TempDir::new().unwrap();
+        let project = TempDir::new().unwrap();
+        let target = project.path().join("a.txt");
+        let cache = ResultCache::new(cache_dir.path());
+
+        let entry = cache.entry(&target, &cached_rules(), "a\n").unwrap();
+        assert!(!entry.is_formatted());
+        entry.mark_formatted().unwrap();
+
+        let cache = ResultCache::new(cache_dir.path());
+        let entry = |rules: &[Rule], content| cache.entry(&target, rules, content).unwrap();
+        assert!(entry(&cached_rules(), "a\n").is_formatted());
+
+        // Other content of the same file
+        assert!(!entry(&cached_rules(), "b\n").is_formatted());
+
+        // Other rules
+        let other_rules =
+            rules(r#"[{"on": ".txt", "cmd": {"io": "tr a-z A-Z"}, "cache": {"inputs": []}}]"#);
+        assert!(!entry(&other_rules, "a\n").is_formatted());
+    }
+
+    #[test]
+    fn test_result_cache_formatter_config_files() {
+        let cache_dir = TempDir::new().unwrap();
+        let project = TempDir::new().unwrap();
+        let target = project.path().join("a.rs");
+        let rustfmt =
+            rules(r#"[{"on": ".rs", "cmd": "https://example.com/download/foro-rustfmt.dllpack"}]"#);
+        let is_formatted = || {
+            ResultCache::new(cache_dir.path())
+                .entry(&target, &rustfmt, "fn main() {}\n")
+                .unwrap()
+                .is_formatted()
+        };
+
+        // Rules are cached without `cache`
+        ResultCache::new(cache_dir.path())
+            .entry(&target, &rustfmt, "fn main() {}\n")
+            .unwrap()
+            .mark_formatted()
+            .unwrap();
+        assert!(is_formatted());
+
+        // The config files of other formatters don't invalidate the entry
+        fs::write(project.path().join("biome.json"), "{}\n").unwrap();
+        assert!(is_formatted());
+
+        // But the plugin's own do
+        fs::write(project.path().join("rustfmt.toml"), "max_width = 80\n").unwrap();
+        assert!(!is_formatted());
+
+        assert_eq!(
+            plugin_name(&Url::parse("https://example.com/foro-clang-format.dllpack").unwrap()),
+            Some("clang-format")
+        );
+        // Unknown formatters may read any of the known config files
+        assert!(formatter_config_files(Some("unknown")).contains(&"rustfmt.toml"));
+    }
+
+    #[test]
+    fn test_result_cache_inputs() {
+        let cache_dir = TempDir::new().unwrap();
+        let project = TempDir::new().unwrap();
+        fs::create_dir(project.path().join("src")).unwrap();
+        let target = project.path().join("src").join("a.txt");
+
+        ResultCache::new(cache_dir.path())
+            .entry(&target, &cached_rules(), "a\n")
+            .unwrap()
+            .mark_formatted()
+            .unwrap();
+
+        // An input in an ancestor directory invalidates the entry
+        fs::write(project.path().join("fmt.toml"), "width = 80\n").unwrap();
+        let entry = ResultCache::new(cache_dir.path())
+            .entry(&target, &cached_rules(), "a\n")
+            .unwrap();
+        assert!(!entry.is_formatted());
+        entry.mark_formatted().unwrap();
+
+        // And so does a change to it
+        fs::write(project.path().join("fmt.toml"), "width = 100\n").unwrap();
+        let entry = ResultCache::new(cache_dir.path())
+            .entry(&target, &cached_rules(), "a\n")
+            .unwrap();
+        assert!(!entry.is_formatted());
+
+        // Files that aren't inputs don't
+        entry.mark_formatted().unwrap();
+        fs::write(project.path().join("notes.txt"), "a\n").unwrap();
+        assert!(ResultCache::new(cache_dir.path())
+            .entry(&target, &cached_rules(), "a\n")
+            .unwrap()
+            .is_formatted());
+    }
+
+    #[test]
+    fn test_result_cache_prune() {
+        let cache_dir = TempDir::new().unwrap();
+        let project = TempDir::new().unwrap();
+        let cache = ResultCache::new(cache_dir.path());
+
+        let old = cache
+            .entry(&project.path().join("old.txt"), &cached_rules(), "a\n")
+            .unwrap();
+        let new = cache
+            .entry(&project.path().join("new.txt"), &cached_rules(), "a\n")
+            .unwrap();
+        old.mark_formatted().unwrap();
+        new.mark_formatted().unwrap();
+
+        let long_ago = SystemTime::now() - MAX_AGE - PRUNE_INTERVAL;
+        fs::File::options()
+            .write(true)
+            .open(&old.path)
+            .unwrap()
+            .set_modified(long_ago)
+            .unwrap();
+
+        cache.prune().unwrap();
+
+        assert!(!old.is_formatted());
+        assert!(new.is_formatted());
+    }
+}
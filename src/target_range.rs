@@ -0,0 +1,124 @@
+use crate::daemon::interface::TargetRange;
+use crate::diff::line_starts;
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use std::ops::Range;
+
+impl TargetRange {
+    /// The byte offsets of the range in `content`.
+    pub fn resolve(&self, content: &str) -> Result<Range<usize>> {
+        match *self {
+            TargetRange::Bytes { start, end } => {
+                if start > end || end > content.len() {
+                    bail!(
+                        "Byte range {start}:{end} is out of the content ({} bytes)",
+                        content.len()
+                    );
+                }
+                if !content.is_char_boundary(start) || !content.is_char_boundary(end) {
+                    bail!("Byte range {start}:{end} is not on character boundaries");
+                }
+
+                Ok(start..end)
+            }
+            TargetRange::Lines { start, end } => {
+                let starts = line_starts(content);
+                let line_count = starts.len() - 1;
+
+                if start == 0 || start > end || end > line_count {
+                    bail!("Line range {start}:{end} is out of the content ({line_count} lines)");
+                }
+
+                Ok(starts[start - 1]..starts[end])
+            }
+        }
+    }
+}
+
+/// The 1-based line containing `offset`.
+fn line_number(starts: &[usize], offset: usize) -> usize {
+    starts[..starts.len() - 1].partition_point(|&start| start <= offset)
+}
+
+/// The `target-range` of the data-json: the byte offsets, and the lines they span.
+pub fn target_range_json(content: &str, range: &Range<usize>) -> Value {
+    let starts = line_starts(content);
+    let start_line = line_number(&starts, range.start);
+    let end_line = if range.is_empty() {
+        start_line
+    } else {
+        line_number(&starts, range.end - 1)
+    };
+
+    json!({
+        "start": range.start,
+        "end": range.end,
+        "start-line": start_line,
+        "end-line": end_line,
+    })
+}
+
+/// The byte offsets of a `target-range` in the data-json.
+pub fn parse_target_range_json(value: &Value) -> Option<Range<usize>> {
+    let start = value.get("start")?.as_u64()? as usize;
+    let end = value.get("end")?.as_u64()? as usize;
+
+    Some(start..end)
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let content = "a\nbb\nc";
+
+        assert_eq!(
+            TargetRange::Lines { start: 2, end: 2 }.resolve(content)?,
+            2..5
+        );
+        assert_eq!(
+            TargetRange::Lines { start: 2, end: 3 }.resolve(content)?,
+            2..6
+        );
+        assert_eq!(
+            TargetRange::Bytes { start: 1, end: 3 }.resolve(content)?,
+            1..3
+        );
+
+        assert!(TargetRange::Lines { start: 0, end: 1 }
+            .resolve(content)
+            .is_err());
+        assert!(TargetRange::Lines { start: 3, end: 4 }
+            .resolve(content)
+            .is_err());
+        assert!(TargetRange::Bytes { start: 3, end: 1 }
+            .resolve(content)
+            .is_err());
+        assert!(TargetRange::Bytes { start: 1, end: 2 }
+            .resolve("é")
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_range_json() {
+        let content = "a\nbb\nc";
+
+        assert_eq!(
+            target_range_json(content, &(2..5)),
+            json!({"start": 2, "end": 5, "start-line": 2, "end-line": 2})
+        );
+        assert_eq!(
+            target_range_json(content, &(1..6)),
+            json!({"start": 1, "end": 6, "start-line": 1, "end-line": 3})
+        );
+        assert_eq!(
+            parse_target_range_json(&target_range_json(content, &(3..3))),
+            Some(3..3)
+        );
+    }
+}
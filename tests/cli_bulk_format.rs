@@ -149,3 +149,55 @@ fn test_cli_bulk_format_error_count() {
     // File should stay untouched because formatter execution failed.
     env.assert_eq("input/main.txt", "expected/main.txt");
 }
+
+#[test]
+#[serial]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_bulk_format_check() {
+    let env = TestEnvBuilder::new()
+        .fixture_path("./tests/fixtures/cli_bulk_format/check/")
+        .work_dir("./input/")
+        .build();
+
+    let mut cmd = env.foro_cmd(&["format", ".", "--check"]);
+    let output = std::process::Command::output(&mut cmd).unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Would reformat: unformatted.txt"));
+    assert!(!stdout.contains("Would reformat: formatted.txt"));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("2 files checked."));
+    assert!(stderr.contains("1 would be reformatted"));
+    assert!(stderr.contains("1 already formatted"));
+
+    // Nothing should be written in check mode.
+    env.assert_eq("input/formatted.txt", "expected/formatted.txt");
+    env.assert_eq("input/unformatted.txt", "expected/unformatted.txt");
+}
+
+#[test]
+#[serial]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_bulk_format_project_config() {
+    let env = TestEnvBuilder::new()
+        .fixture_path("./tests/fixtures/cli_bulk_format/project_config/")
+        .work_dir("./input/")
+        .build();
+
+    // Without `--config-file`, each file uses the nearest foro.json.
+    let cache_dir = env.cache.path();
+    let socket_dir = env.socket_dir.path().to_path_buf();
+    let mut cmd = env.raw_foro(&[
+        "format",
+        ".",
+        "--cache-dir",
+        cache_dir.to_str().unwrap(),
+        "--socket-dir",
+        socket_dir.to_str().unwrap(),
+    ]);
+    let output = std::process::Command::output(&mut cmd).unwrap();
+    assert!(output.status.success(), "Command failed: {output:?}");
+
+    env.assert_eq("input/root.txt", "expected/root.txt");
+    env.assert_eq("input/sub/nested.txt", "expected/sub/nested.txt");
+}
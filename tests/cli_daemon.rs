@@ -84,3 +84,227 @@ fn test_cli_daemon_start_recovers_from_malformed_info() {
     let res = env.foro_stdout(&["daemon", "ping"]);
     assert!(res.contains("pong!"));
 }
+
+#[cfg(unix)]
+mod framed {
+    use crate::common::TestEnv;
+    use serde_json::{json, Value};
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    pub fn connect(env: &TestEnv) -> UnixStream {
+        let mut stream =
+            UnixStream::connect(env.socket_dir.path().join("daemon-cmd.sock")).unwrap();
+        stream.write_all(b"\0foro-framed-v1\n").unwrap();
+        stream
+    }
+
+    pub fn write_frame(stream: &mut UnixStream, message: Value) {
+        let body = message.to_string();
+        stream
+            .write_all(&(body.len() as u32).to_be_bytes())
+            .unwrap();
+        stream.write_all(body.as_bytes()).unwrap();
+    }
+
+    pub fn read_frame(stream: &mut UnixStream) -> Value {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut body = vec![0; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    pub fn payload(env: &TestEnv, command: Value) -> Value {
+        json!({
+            "command": command,
+            "current_dir": env.work_dir.path(),
+            "execution_options": {
+                "config_file": env.config_file.path(),
+                "cache_dir": env.cache.path(),
+                "socket_dir": env.socket_dir.path(),
+                "ignore_build_id_mismatch": false,
+            },
+        })
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_cli_daemon_framed_connection() {
+    use framed::{connect, payload, read_frame, write_frame};
+    use serde_json::json;
+
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_check/");
+    env.foro(&["daemon", "start"]);
+
+    let mut stream = connect(&env);
+
+    // Several requests are pipelined on one connection
+    for (id, content) in [(1, "first\n"), (2, "second\n")] {
+        write_frame(
+            &mut stream,
+            json!({
+                "id": id,
+                "payload": payload(&env, json!({"Format": {
+                    "path": "new.txt",
+                    "content": content,
+                    "return_content": true,
+                }})),
+            }),
+        );
+    }
+
+    let mut responses = [read_frame(&mut stream), read_frame(&mut stream)];
+    responses.sort_by_key(|response| response["id"].as_u64());
+
+    assert_eq!(
+        responses,
+        [
+            json!({"id": 1, "response": {"Format": {"Content": "FIRST\n"}}}),
+            json!({"id": 2, "response": {"Format": {"Content": "SECOND\n"}}}),
+        ]
+    );
+
+    env.foro(&["daemon", "stop"]);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_cli_daemon_drops_malformed_connection() {
+    use framed::connect;
+    use std::io::{Read, Write};
+
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_check/");
+    env.foro(&["daemon", "start"]);
+
+    let mut stream = connect(&env);
+    stream.write_all(&5u32.to_be_bytes()).unwrap();
+    stream.write_all(b"{bad}").unwrap();
+
+    // The connection is closed, and the daemon keeps serving others
+    assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+    env.foro(&["daemon", "ping"]);
+
+    env.foro(&["daemon", "stop"]);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_cli_daemon_bulk_format_progress() {
+    use framed::{connect, payload, read_frame, write_frame};
+    use serde_json::json;
+
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_check/");
+    env.foro(&["daemon", "start"]);
+
+    let mut stream = connect(&env);
+    write_frame(
+        &mut stream,
+        json!({
+            "id": 7,
+            "payload": payload(&env, json!({"BulkFormat": {
+                "paths": ["main.txt"],
+                "threads": 1,
+                "check": true,
+                "progress": true,
+            }})),
+        }),
+    );
+
+    let main = env.path("main.txt").canonicalize().unwrap();
+
+    // Progress events come before the response, with the id of the request
+    assert_eq!(
+        read_frame(&mut stream),
+        json!({"id": 7, "response": {"BulkFormatProgress": {"Started": main}}})
+    );
+    assert_eq!(
+        read_frame(&mut stream),
+        json!({"id": 7, "response": {"BulkFormatProgress": {"Finished": {
+            "result": {"path": main, "status": "changed"},
+            "totals": {
+                "found_count": 1,
+                "changed_count": 1,
+                "unchanged_count": 0,
+                "ignored_count": 0,
+                "error_count": 0,
+            },
+        }}}})
+    );
+
+    let response = read_frame(&mut stream);
+    assert_eq!(response["id"], 7);
+    assert_eq!(
+        response["response"]["BulkFormat"]["Success"]["changed_count"],
+        1
+    );
+
+    env.foro(&["daemon", "stop"]);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_cli_daemon_bulk_format_cancel() {
+    use framed::{connect, payload, read_frame, write_frame};
+    use serde_json::json;
+
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_daemon_cancel/");
+    env.foro(&["daemon", "start"]);
+
+    let mut stream = connect(&env);
+    write_frame(
+        &mut stream,
+        json!({
+            "id": 1,
+            "payload": payload(&env, json!({"BulkFormat": {
+                "paths": ["files"],
+                "threads": 1,
+                "progress": true,
+            }})),
+        }),
+    );
+
+    // Each file takes a second, so the request is still running
+    let started = read_frame(&mut stream);
+    assert!(started["response"]["BulkFormatProgress"]["Started"].is_string());
+
+    write_frame(
+        &mut stream,
+        json!({"id": 2, "payload": payload(&env, json!({"Cancel": {"id": 1}}))}),
+    );
+
+    let mut cancel_response = None;
+    let summary = loop {
+        let frame = read_frame(&mut stream);
+        match frame["id"].as_u64() {
+            Some(2) => cancel_response = Some(frame["response"].clone()),
+            Some(1) if frame["response"].get("BulkFormat").is_some() => {
+                break frame["response"]["BulkFormat"]["Success"].clone();
+            }
+            _ => {}
+        }
+    };
+
+    assert_eq!(cancel_response, Some(json!({"Cancel": true})));
+    assert_eq!(summary["cancelled"], true);
+    assert!(summary["total_count"].as_u64().unwrap() < 5);
+
+    // The files after the cancellation are left untouched
+    assert_eq!(
+        std::fs::read_to_string(env.path("files/e.txt")).unwrap(),
+        "x\n"
+    );
+
+    // Nothing is running anymore
+    write_frame(
+        &mut stream,
+        json!({"id": 3, "payload": payload(&env, json!({"Cancel": {"id": 1}}))}),
+    );
+    assert_eq!(
+        read_frame(&mut stream),
+        json!({"id": 3, "response": {"Cancel": false}})
+    );
+
+    env.foro(&["daemon", "stop"]);
+}
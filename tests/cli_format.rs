@@ -255,3 +255,296 @@ fn test_cli_format_recovers_from_malformed_daemon_info() {
     env.foro(&["format", "./main.rs"]);
     env.assert_eq("main.rs", "expected.rs");
 }
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_format_check() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_check/");
+
+    let output = env
+        .foro_cmd(&["format", "./main.txt", "--check"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Would reformat: main.txt"));
+
+    // The file must be left untouched.
+    env.assert_eq("main.txt", "expected.txt");
+}
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_format_diff() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_check/");
+
+    let stdout = env.foro_stdout(&["format", "./main.txt", "--diff"]);
+    assert_eq!(
+        stdout,
+        "--- a/main.txt\n+++ b/main.txt\n@@ -1 +1 @@\n-hello\n+HELLO\n"
+    );
+
+    // The file must be left untouched.
+    env.assert_eq("main.txt", "expected.txt");
+}
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_format_stdin() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_check/");
+
+    // The path doesn't need to exist, it's only used to select the rule.
+    let cmd = env.foro_cmd(&["format", "--stdin-filepath", "./new.txt"]);
+    assert_cmd::Command::from_std(cmd)
+        .write_stdin("hello\n")
+        .assert()
+        .success()
+        .stdout("HELLO\n");
+
+    // Content without a matching rule is passed through as is.
+    let cmd = env.foro_cmd(&["format", "--stdin-filepath", "./notes.md"]);
+    assert_cmd::Command::from_std(cmd)
+        .write_stdin("hello\n")
+        .assert()
+        .success()
+        .stdout("hello\n");
+
+    assert!(!env.child("new.txt").exists());
+    env.assert_eq("main.txt", "expected.txt");
+}
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_format_stdin_check() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_check/");
+
+    // With --check, the content is never echoed, only the status is reported.
+    let cmd = env.foro_cmd(&["format", "--stdin-filepath", "./new.txt", "--check"]);
+    assert_cmd::Command::from_std(cmd)
+        .write_stdin("HELLO\n")
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(predicates::str::contains("Already formatted."));
+
+    let cmd = env.foro_cmd(&["format", "--stdin-filepath", "./new.txt", "--check"]);
+    assert_cmd::Command::from_std(cmd)
+        .write_stdin("hello\n")
+        .assert()
+        .failure()
+        .stdout("Would reformat: new.txt\n");
+
+    assert!(!env.child("new.txt").exists());
+}
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_format_range() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_check/");
+
+    // The plugin formats everything, but only the selected lines are changed.
+    let cmd = env.foro_cmd(&["format", "--stdin-filepath", "./new.txt", "--lines", "2:3"]);
+    assert_cmd::Command::from_std(cmd)
+        .write_stdin("a\nb\nc\nd\n")
+        .assert()
+        .success()
+        .stdout("a\nB\nC\nd\n");
+
+    let cmd = env.foro_cmd(&["format", "--stdin-filepath", "./new.txt", "--bytes", "0:1"]);
+    assert_cmd::Command::from_std(cmd)
+        .write_stdin("a\nb\n")
+        .assert()
+        .success()
+        .stdout("A\nb\n");
+
+    env.child("range.txt").write_str("a\nb\nc\n").unwrap();
+    env.foro(&["format", "./range.txt", "--lines", "3:3"]);
+    assert_eq!(
+        std::fs::read_to_string(env.path("range.txt")).unwrap(),
+        "a\nb\nC\n"
+    );
+
+    let output = env
+        .foro_cmd(&["format", "./range.txt", "--lines", "3:4"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_format_edits() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_check/");
+
+    let cmd = env.foro_cmd(&["format", "--stdin-filepath", "./new.txt", "--edits"]);
+    assert_cmd::Command::from_std(cmd)
+        .write_stdin("ok\nhello\n")
+        .assert()
+        .success()
+        .stdout(concat!(
+            r#"[{"start":0,"end":2,"start_position":{"line":0,"character":0},"#,
+            r#""end_position":{"line":0,"character":2},"new_text":"OK"},"#,
+            r#"{"start":3,"end":8,"start_position":{"line":1,"character":0},"#,
+            r#""end_position":{"line":1,"character":5},"new_text":"HELLO"}]"#,
+            "\n"
+        ));
+
+    // The file is left untouched
+    env.foro_cmd(&["format", "./main.txt", "--edits"])
+        .assert()
+        .success();
+    env.assert_eq("main.txt", "expected.txt");
+
+    let cmd = env.foro_cmd(&["format", "--stdin-filepath", "./notes.md", "--edits"]);
+    assert_cmd::Command::from_std(cmd)
+        .write_stdin("hello\n")
+        .assert()
+        .success()
+        .stdout("[]\n");
+}
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_format_mode_all() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_mode_all/");
+
+    // Both rules run, the second one on the output of the first one.
+    env.foro(&["format", "./main.txt"]);
+    env.assert_eq("main.txt", "expected.txt");
+}
+
+#[test]
+fn test_cli_format_reloads_changed_config() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_mode_all/");
+
+    env.foro(&["format", "./main.txt"]);
+    env.assert_eq("main.txt", "expected.txt");
+
+    // The running daemon picks up the edited config without a restart.
+    env.config_file
+        .write_str(r#"{"rules": [{"on": ".txt", "cmd": {"io": "tr a-z A-Z"}}]}"#)
+        .unwrap();
+
+    let main = env.child("main.txt");
+    let start = std::time::Instant::now();
+
+    loop {
+        main.write_str("hello\n").unwrap();
+        env.foro(&["format", "./main.txt"]);
+
+        if std::fs::read_to_string(main.path()).unwrap() == "HELLO\n" {
+            break;
+        }
+
+        assert!(
+            start.elapsed() < std::time::Duration::from_secs(5),
+            "The daemon didn't reload the config"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_format_output_format() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_report/");
+
+    let output = env
+        .foro_cmd(&["format", "--check", "--output-format", "json"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let files: Vec<_> = report["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| {
+            (
+                file["path"].as_str().unwrap(),
+                file["status"].as_str().unwrap(),
+            )
+        })
+        // The socket directory of the test env is in the work dir
+        .filter(|(path, _)| !path.starts_with("socket"))
+        .collect();
+    assert_eq!(
+        files,
+        [
+            ("broken.bad", "error"),
+            ("formatted.txt", "unchanged"),
+            ("foro.json", "ignored"),
+            ("main.txt", "changed"),
+            ("notes.md", "ignored"),
+        ]
+    );
+    assert!(report["files"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("Failed to execute command"));
+
+    let stdout = String::from_utf8(
+        env.foro_cmd(&["format", "--check", "--output-format", "checkstyle"])
+            .output()
+            .unwrap()
+            .stdout,
+    )
+    .unwrap();
+    assert!(stdout.contains(r#"<file name="broken.bad">"#));
+    assert!(stdout.contains(r#"<file name="main.txt">"#));
+    assert!(!stdout.contains("formatted.txt"));
+
+    // The files are left untouched in check mode
+    assert_eq!(
+        std::fs::read_to_string(env.path("main.txt")).unwrap(),
+        "hello\n"
+    );
+}
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_format_reports_file_errors() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_report/");
+
+    let output = env.foro_cmd(&["format"]).output().unwrap();
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Error: broken.bad: Failed to execute command"),
+        "{stderr}"
+    );
+    assert_eq!(
+        std::fs::read_to_string(env.path("main.txt")).unwrap(),
+        "HELLO\n"
+    );
+}
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_format_result_cache() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_result_cache/");
+    let runs = || {
+        std::fs::read_to_string(env.path("src/runs.log"))
+            .unwrap_or_default()
+            .lines()
+            .count()
+    };
+
+    env.foro(&["format", "src"]);
+    assert_eq!(runs(), 2);
+
+    // Files known to be formatted aren't sent through the formatter again
+    env.foro(&["format", "src"]);
+    assert_eq!(runs(), 2);
+
+    std::fs::write(env.path("src/a.txt"), "c\n").unwrap();
+    env.foro(&["format", "src"]);
+    assert_eq!(runs(), 3);
+
+    // A config file of a formatter invalidates the results
+    std::fs::write(env.path(".editorconfig"), "root = true\n").unwrap();
+    env.foro(&["format", "src"]);
+    assert_eq!(runs(), 5);
+}
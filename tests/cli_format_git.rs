@@ -0,0 +1,160 @@
+mod common;
+
+use crate::common::TestEnv;
+use assert_fs::prelude::*;
+use std::fs;
+use std::process::Command;
+
+fn git(env: &TestEnv, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(env.work_dir.path())
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {args:?} failed: {output:?}");
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn init_repo(env: &TestEnv) {
+    git(env, &["init", "-q"]);
+    git(env, &["config", "user.email", "foro@example.com"]);
+    git(env, &["config", "user.name", "foro"]);
+}
+
+#[test]
+fn test_cli_format_staged_keeps_unstaged_changes() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_git/");
+    init_repo(&env);
+
+    env.child("partial.txt").write_str("staged\n").unwrap();
+    env.child("full.txt").write_str("full\n").unwrap();
+    env.child("unstaged.txt").write_str("unstaged\n").unwrap();
+    git(&env, &["add", "partial.txt", "full.txt"]);
+    env.child("partial.txt")
+        .write_str("staged\nnot staged\n")
+        .unwrap();
+
+    env.foro(&["format", "--staged"]);
+
+    // The staged content is formatted, the unstaged hunk is kept as is
+    assert_eq!(git(&env, &["show", ":partial.txt"]), "STAGED\n");
+    assert_eq!(
+        fs::read_to_string(env.path("partial.txt")).unwrap(),
+        "staged\nnot staged\n"
+    );
+
+    // Without unstaged changes, the work tree is updated too
+    assert_eq!(git(&env, &["show", ":full.txt"]), "FULL\n");
+    assert_eq!(fs::read_to_string(env.path("full.txt")).unwrap(), "FULL\n");
+
+    assert_eq!(
+        fs::read_to_string(env.path("unstaged.txt")).unwrap(),
+        "unstaged\n"
+    );
+}
+
+#[test]
+fn test_cli_format_staged_check() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_git/");
+    init_repo(&env);
+
+    env.child("a.txt").write_str("a\n").unwrap();
+    git(&env, &["add", "a.txt"]);
+
+    let output = env
+        .foro_cmd(&["format", "--staged", "--check"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Would reformat: a.txt\n"
+    );
+    assert_eq!(git(&env, &["show", ":a.txt"]), "a\n");
+}
+
+#[test]
+fn test_cli_format_changed() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_git/");
+    init_repo(&env);
+
+    env.child("committed.txt").write_str("committed\n").unwrap();
+    env.child("modified.txt").write_str("modified\n").unwrap();
+    git(&env, &["add", "."]);
+    git(&env, &["commit", "-q", "-m", "init"]);
+
+    env.child("modified.txt")
+        .write_str("modified again\n")
+        .unwrap();
+    env.child("untracked.txt").write_str("untracked\n").unwrap();
+
+    env.foro(&["format", "--changed"]);
+
+    assert_eq!(
+        fs::read_to_string(env.path("committed.txt")).unwrap(),
+        "committed\n"
+    );
+    assert_eq!(
+        fs::read_to_string(env.path("modified.txt")).unwrap(),
+        "MODIFIED AGAIN\n"
+    );
+    assert_eq!(
+        fs::read_to_string(env.path("untracked.txt")).unwrap(),
+        "UNTRACKED\n"
+    );
+
+    git(&env, &["add", "."]);
+    git(&env, &["commit", "-q", "-m", "format"]);
+    env.child("later.txt").write_str("later\n").unwrap();
+    git(&env, &["add", "later.txt"]);
+    git(&env, &["commit", "-q", "-m", "later"]);
+
+    env.foro(&["format", "--changed-since", "HEAD~1"]);
+
+    assert_eq!(
+        fs::read_to_string(env.path("later.txt")).unwrap(),
+        "LATER\n"
+    );
+    assert_eq!(
+        fs::read_to_string(env.path("committed.txt")).unwrap(),
+        "committed\n"
+    );
+}
+
+#[test]
+fn test_cli_format_staged_deleted_from_work_tree() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_git/");
+    init_repo(&env);
+
+    env.child("deleted.txt").write_str("deleted\n").unwrap();
+    env.child("kept.txt").write_str("kept\n").unwrap();
+    git(&env, &["add", "deleted.txt", "kept.txt"]);
+    fs::remove_file(env.path("deleted.txt")).unwrap();
+
+    env.foro(&["format", "--staged"]);
+
+    // Only the index is updated, the deletion is kept
+    assert_eq!(git(&env, &["show", ":deleted.txt"]), "DELETED\n");
+    assert!(!env.path("deleted.txt").exists());
+    assert_eq!(fs::read_to_string(env.path("kept.txt")).unwrap(), "KEPT\n");
+}
+
+#[test]
+#[cfg(unix)]
+fn test_cli_format_staged_ignores_symlinks() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_git/");
+    init_repo(&env);
+
+    env.child("target.txt").write_str("target\n").unwrap();
+    std::os::unix::fs::symlink("target.txt", env.path("link.txt")).unwrap();
+    git(&env, &["add", "link.txt"]);
+
+    env.foro(&["format", "--staged"]);
+
+    assert_eq!(git(&env, &["show", ":link.txt"]), "target.txt");
+    assert!(fs::symlink_metadata(env.path("link.txt"))
+        .unwrap()
+        .file_type()
+        .is_symlink());
+}
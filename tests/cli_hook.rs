@@ -0,0 +1,112 @@
+#![cfg(unix)]
+
+mod common;
+
+use crate::common::{TestEnv, TestEnvBuilder};
+use assert_fs::prelude::*;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+fn git_cmd(env: &TestEnv, args: &[&str]) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.current_dir(env.work_dir.path()).args(args);
+    cmd
+}
+
+fn git(env: &TestEnv, args: &[&str]) {
+    let output = git_cmd(env, args).output().unwrap();
+    assert!(output.status.success(), "git {args:?} failed: {output:?}");
+}
+
+fn init_repo() -> TestEnv {
+    let env = TestEnvBuilder::new()
+        .fixture_path("./tests/fixtures/cli_format_git/")
+        .build_without_install();
+
+    git(&env, &["init", "-q"]);
+    git(&env, &["config", "user.email", "foro@example.com"]);
+    git(&env, &["config", "user.name", "foro"]);
+
+    env
+}
+
+fn write_script(path: &Path, content: &str) {
+    fs::write(path, content).unwrap();
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn test_cli_hook_install_chains_existing_hook() {
+    let env = init_repo();
+    let hook = env.path(".git/hooks/pre-commit");
+
+    let existing_hook = "#!/bin/sh\necho existing >> hook.log\n";
+    write_script(&hook, existing_hook);
+
+    env.foro(&["hook", "install"]);
+    assert_eq!(
+        fs::read_to_string(env.path(".git/hooks/pre-commit.foro-chained")).unwrap(),
+        existing_hook
+    );
+
+    // Installing again keeps the chained hook as is
+    env.foro(&["hook", "install"]);
+
+    // A fake foro, so that committing doesn't depend on the daemon
+    let bin_dir = env.child("bin");
+    bin_dir.create_dir_all().unwrap();
+    write_script(
+        &bin_dir.path().join("foro"),
+        "#!/bin/sh\necho \"foro $*\" >> hook.log\n",
+    );
+    let path = format!(
+        "{}:{}",
+        bin_dir.path().display(),
+        std::env::var("PATH").unwrap()
+    );
+
+    env.child("a.txt").write_str("a\n").unwrap();
+    git(&env, &["add", "a.txt"]);
+    let output = git_cmd(&env, &["commit", "-q", "-m", "test"])
+        .env("PATH", path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git commit failed: {output:?}");
+
+    assert_eq!(
+        fs::read_to_string(env.path("hook.log")).unwrap(),
+        "existing\nforo format --staged\n"
+    );
+
+    env.foro(&["hook", "uninstall"]);
+    assert_eq!(fs::read_to_string(&hook).unwrap(), existing_hook);
+    assert!(!env.path(".git/hooks/pre-commit.foro-chained").exists());
+}
+
+#[test]
+fn test_cli_hook_failing_chained_hook_aborts_commit() {
+    let env = init_repo();
+    write_script(&env.path(".git/hooks/pre-commit"), "#!/bin/sh\nexit 1\n");
+
+    env.foro(&["hook", "install"]);
+
+    env.child("a.txt").write_str("a\n").unwrap();
+    git(&env, &["add", "a.txt"]);
+    let output = git_cmd(&env, &["commit", "-q", "-m", "test"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cli_hook_uninstall_keeps_other_hooks() {
+    let env = init_repo();
+    let hook = env.path(".git/hooks/pre-commit");
+    write_script(&hook, "#!/bin/sh\n");
+
+    let output = env.foro_cmd(&["hook", "uninstall"]).output().unwrap();
+    assert!(!output.status.success());
+    assert_eq!(fs::read_to_string(&hook).unwrap(), "#!/bin/sh\n");
+}
@@ -1,6 +1,7 @@
 mod common;
 
 use crate::common::TestEnvBuilder;
+use assert_fs::prelude::*;
 
 /// install なし → format がエラー、install 後 → format が通る
 #[test]
@@ -70,3 +71,66 @@ fn test_cli_install_whitespace_change_keeps_marker_valid() {
         "format should still succeed after whitespace-only config change"
     );
 }
+
+/// パスを渡すと、その下のプロジェクト config もまとめて install されるか
+#[test]
+fn test_cli_install_project_configs_under_paths() {
+    let env = TestEnvBuilder::new()
+        .fixture_path("./tests/fixtures/cli_install/")
+        .cache_dir("cache")
+        .build_without_install();
+
+    // cache_dir が別なので、サブプロジェクトには別の install が必要
+    let config_with_cache = |cache: &str| {
+        format!(
+            r#"{{"rules": [], "cache_dir": {:?}}}"#,
+            env.path(cache).to_str().unwrap()
+        )
+    };
+    env.config_file
+        .write_str(&config_with_cache("cache"))
+        .unwrap();
+    env.child("sub/foro.json")
+        .write_str(&config_with_cache("cache-sub"))
+        .unwrap();
+    env.child("sub/main.rs")
+        .write_str("fn main() {}\n")
+        .unwrap();
+    // 取得できない plugin なので、install されるとエラーになる
+    env.child("node_modules/pkg/foro.json")
+        .write_str(r#"{"rules": [{"on": ".rs", "cmd": "https://example.invalid/a.dllpack"}]}"#)
+        .unwrap();
+    env.child("node_modules/pkg/main.rs")
+        .write_str("fn main() {}\n")
+        .unwrap();
+
+    // --config-file なしで、各ファイルは最寄りの foro.json を使う
+    let socket_dir = env.socket_dir.path().to_path_buf();
+    let foro = |args: &[&str]| {
+        let mut args = args.to_vec();
+        args.extend(["--socket-dir", socket_dir.to_str().unwrap()]);
+        env.raw_foro(&args).output().unwrap()
+    };
+
+    // カレントディレクトリの config だけでは、サブプロジェクトは install されない
+    assert!(foro(&["install"]).status.success());
+    assert!(!foro(&["format", "sub/main.rs"]).status.success());
+
+    let out = foro(&["install", "."]);
+    assert!(out.status.success(), "install failed: {out:?}");
+
+    // サブプロジェクトの config も install 済みか
+    let out = foro(&["format", "sub/main.rs"]);
+    assert!(
+        out.status.success(),
+        "format should succeed in the sub-project, stderr: {}",
+        String::from_utf8(out.stderr).unwrap()
+    );
+
+    // 無視されるディレクトリの config は install されないか
+    let out = foro(&["format", "node_modules/pkg/main.rs"]);
+    assert!(
+        !out.status.success(),
+        "configs in ignored directories should not be installed"
+    );
+}
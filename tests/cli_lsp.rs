@@ -0,0 +1,146 @@
+mod common;
+
+use crate::common::TestEnv;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{ChildStdin, ChildStdout, Stdio};
+
+struct LspClient {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl LspClient {
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn recv(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.stdout.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                length = value.parse().unwrap();
+            }
+        }
+
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn request(&mut self, id: u64, method: &str, params: Value) -> Value {
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}));
+        let response = self.recv();
+        assert_eq!(response["id"], id, "Unexpected response: {response}");
+        response
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({"jsonrpc": "2.0", "method": method, "params": params}));
+    }
+}
+
+fn edit(line: u32, start: u32, end: u32, new_text: &str) -> Value {
+    json!({
+        "range": {
+            "start": {"line": line, "character": start},
+            "end": {"line": line, "character": end},
+        },
+        "newText": new_text,
+    })
+}
+
+#[test]
+#[cfg_attr(target_os = "windows", ignore = "CommandIO is unsupported on Windows")]
+fn test_cli_lsp_formatting() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_format_check/");
+
+    let mut child = env
+        .foro_cmd(&["lsp"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut client = LspClient {
+        stdin: child.stdin.take().unwrap(),
+        stdout: BufReader::new(child.stdout.take().unwrap()),
+    };
+
+    let response = client.request(1, "initialize", json!({"capabilities": {}}));
+    assert_eq!(
+        response["result"]["capabilities"]["documentFormattingProvider"],
+        true
+    );
+    client.notify("initialized", json!({}));
+
+    // The open document doesn't need to be saved
+    let uri = url::Url::from_file_path(env.path("new.txt"))
+        .unwrap()
+        .to_string();
+    let document = json!({"uri": uri});
+    client.notify(
+        "textDocument/didOpen",
+        json!({"textDocument": {"uri": uri, "languageId": "plaintext", "version": 1, "text": "ok\nhello\n"}}),
+    );
+
+    let response = client.request(
+        2,
+        "textDocument/formatting",
+        json!({"textDocument": document, "options": {"tabSize": 4, "insertSpaces": true}}),
+    );
+    assert_eq!(
+        response["result"],
+        json!([edit(0, 0, 2, "OK"), edit(1, 0, 5, "HELLO")])
+    );
+
+    let response = client.request(
+        3,
+        "textDocument/rangeFormatting",
+        json!({
+            "textDocument": document,
+            "range": {"start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 5}},
+            "options": {"tabSize": 4, "insertSpaces": true},
+        }),
+    );
+    assert_eq!(response["result"], json!([edit(1, 0, 5, "HELLO")]));
+
+    client.notify(
+        "textDocument/didChange",
+        json!({"textDocument": {"uri": uri, "version": 2}, "contentChanges": [{"text": "OK\nbye\n"}]}),
+    );
+    let response = client.request(
+        4,
+        "textDocument/willSaveWaitUntil",
+        json!({"textDocument": document, "reason": 1}),
+    );
+    assert_eq!(response["result"], json!([edit(1, 0, 3, "BYE")]));
+
+    // A file without a matching rule is ignored
+    let uri = url::Url::from_file_path(env.path("notes.md"))
+        .unwrap()
+        .to_string();
+    client.notify(
+        "textDocument/didOpen",
+        json!({"textDocument": {"uri": uri, "languageId": "markdown", "version": 1, "text": "hello\n"}}),
+    );
+    let response = client.request(
+        5,
+        "textDocument/formatting",
+        json!({"textDocument": {"uri": uri}, "options": {"tabSize": 4, "insertSpaces": true}}),
+    );
+    assert_eq!(response["result"], Value::Null);
+
+    client.request(6, "shutdown", Value::Null);
+    client.notify("exit", Value::Null);
+
+    assert!(child.wait().unwrap().success());
+}
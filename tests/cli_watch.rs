@@ -0,0 +1,55 @@
+mod common;
+
+use crate::common::TestEnv;
+use assert_fs::prelude::*;
+use std::fs;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+fn wait_for_content(path: &std::path::Path, expected: &str) {
+    let start = Instant::now();
+
+    while fs::read_to_string(path).unwrap() != expected {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "{path:?} was not formatted"
+        );
+        sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_cli_watch_formats_saved_files() {
+    let env = TestEnv::new_fixture("./tests/fixtures/cli_watch/");
+
+    let stdout = String::from_utf8(env.foro(&["watch", "."]).stdout).unwrap();
+    assert!(stdout.starts_with("Watching: "), "{stdout}");
+
+    env.child("src/generated/gen.txt")
+        .write_str("hello\n")
+        .unwrap();
+    env.child("src/main.txt")
+        .write_str("hello world\n")
+        .unwrap();
+    wait_for_content(&env.path("src/main.txt"), "HELLO WORLD\n");
+
+    // Files ignored by `.foro-ignore` are left alone
+    assert_eq!(
+        fs::read_to_string(env.path("src/generated/gen.txt")).unwrap(),
+        "hello\n"
+    );
+
+    let stdout = String::from_utf8(env.foro(&["watch", "--list"]).stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1, "{stdout}");
+
+    env.foro(&["watch", "--stop", "."]);
+    let stdout = String::from_utf8(env.foro(&["watch", "--list"]).stdout).unwrap();
+    assert_eq!(stdout, "");
+
+    env.child("src/main.txt").write_str("hello\n").unwrap();
+    sleep(Duration::from_millis(500));
+    assert_eq!(
+        fs::read_to_string(env.path("src/main.txt")).unwrap(),
+        "hello\n"
+    );
+}